@@ -1,86 +1,88 @@
+use crate::{errors::ClientError, Request, Response};
 use bytes::Bytes;
 use std::sync::Arc;
-use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::io::{self, AsyncWriteExt};
 use tokio::net::UnixStream;
 use tokio::sync::Mutex;
 use tokio::time::{interval, Duration, Instant};
 
 #[derive(Clone, Debug)]
 pub struct Client {
-    address: &'static str,
+    address: String,
     stream: Arc<Mutex<UnixStream>>,
 }
 
 impl Client {
-    pub async fn new(address: &'static str) -> Self {
-        let stream = Self::connect(address).await;
+    pub async fn new(address: impl Into<String>) -> io::Result<Self> {
+        let address = address.into();
+        let stream = Self::connect(&address).await?;
 
-        Self {
+        Ok(Self {
             address,
             stream: Arc::new(Mutex::new(stream)),
-        }
+        })
     }
 
-    pub async fn default() -> Self {
+    pub async fn default() -> io::Result<Self> {
         Self::new("/tmp/lsphp.sock").await
     }
 
-    async fn connect(address: &str) -> UnixStream {
+    async fn connect(address: &str) -> io::Result<UnixStream> {
         let timeout = Duration::from_secs(5);
         let mut interval = interval(Duration::from_millis(10));
         let start_time = Instant::now();
 
         loop {
-            tokio::select! {
-                _ = interval.tick() => {
-                    println!("Attempting to connect to {}...", address);
-
-                    if let Ok(stream) = UnixStream::connect(address).await {
-                        println!("Successfully connected to {}", address);
-                        break stream;
-                    }
+            interval.tick().await;
 
-                    if start_time.elapsed() > timeout {
-                        panic!("Failed to connect to {}", address);
-                    }
-                }
+            match UnixStream::connect(address).await {
+                Ok(stream) => break Ok(stream),
+                Err(error) if start_time.elapsed() > timeout => break Err(error),
+                Err(_) => continue,
             }
         }
     }
 
     pub fn address(&self) -> &str {
-        self.address
+        &self.address
     }
 
     pub fn stream(&self) -> Arc<Mutex<UnixStream>> {
         self.stream.clone()
     }
+
+    /// Replaces the connection with a new one, like after the server restarted.
+    pub async fn reconnect(&self) -> io::Result<()> {
+        let stream = Self::connect(&self.address).await?;
+        *self.stream.lock().await = stream;
+
+        Ok(())
+    }
 }
 
 impl Client {
     pub async fn send(&mut self, packet: Bytes) -> io::Result<()> {
-        println!("Sending data...");
         let mut stream = self.stream.lock().await;
-        let _ = stream.write_all(&packet).await;
+        stream.write_all(&packet).await?;
         stream.flush().await
     }
 
-    pub async fn receive(&mut self) -> io::Result<Vec<u8>> {
-        println!("Receiving data...");
-        let mut buffer = Vec::new();
-        let mut temp_buf = [0; 1024]; // Temporary buffer
-
-        loop {
-            println!("in the loop");
-            let bytes_read = self.stream.lock().await.read(&mut temp_buf).await?;
-            if bytes_read == 0 {
-                break;
-            } // If no bytes read, we're likely at EOF or client closed
+    /// Sends a request followed by its body and waits for the whole response.
+    ///
+    /// The connection is locked for the whole exchange, as LSAPI handles a
+    /// single request at a time per connection.
+    pub async fn execute(
+        &self,
+        request: Request<'_>,
+        body: &[u8],
+    ) -> Result<Response, ClientError> {
+        let request = request.body_length(body.len() as u32);
+        let mut stream = self.stream.lock().await;
 
-            buffer.extend_from_slice(&temp_buf[..bytes_read]);
-            println!("buffer: {:#?}", buffer);
-        }
+        stream.write_all(&request.into_bytes()).await?;
+        stream.write_all(body).await?;
+        stream.flush().await?;
 
-        Ok(buffer)
+        Response::read_from(&mut *stream).await
     }
 }
@@ -1,5 +1,5 @@
 use bytes::{BufMut, Bytes, BytesMut};
-use std::mem::{size_of, size_of_val};
+use std::mem::size_of;
 
 #[derive(Clone, Debug, Copy)]
 pub struct EnvVariable<'a> {
@@ -19,8 +19,25 @@ impl<'a> EnvVariable<'a> {
         }
     }
 
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn value(&self) -> &'a str {
+        self.value
+    }
+
     pub fn len(&self) -> usize {
-        size_of::<u16>() * 2 + size_of_val(&self.name) + size_of_val(&self.value)
+        size_of::<u16>() * 2 + self.name_length as usize + self.value_length as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.name.is_empty() && self.value.is_empty()
+    }
+
+    /// Offset of the value relative to the start of the encoded variable.
+    pub fn value_offset(&self) -> usize {
+        size_of::<u16>() * 2 + self.name_length as usize
     }
 
     pub fn into_bytes(self) -> Bytes {
@@ -28,15 +45,16 @@ impl<'a> EnvVariable<'a> {
     }
 }
 
-impl<'a> Into<Bytes> for EnvVariable<'a> {
-    fn into(self) -> Bytes {
-        let mut buffer = BytesMut::with_capacity(self.len());
+impl<'a> From<EnvVariable<'a>> for Bytes {
+    fn from(value: EnvVariable<'a>) -> Self {
+        let mut buffer = BytesMut::with_capacity(value.len());
 
-        buffer.put_u16(self.name_length);
-        buffer.put_u16(self.value_length);
-        buffer.extend_from_slice(self.name.as_bytes());
+        // Lengths are always big endian, regardless of the packet endianness.
+        buffer.put_u16(value.name_length);
+        buffer.put_u16(value.value_length);
+        buffer.extend_from_slice(value.name.as_bytes());
         buffer.put_u8(0); // Null terminator required by LiteSpeed protocol.
-        buffer.extend_from_slice(self.value.as_bytes());
+        buffer.extend_from_slice(value.value.as_bytes());
         buffer.put_u8(0); // Null terminator required by LiteSpeed protocol.
 
         buffer.into()
@@ -61,12 +79,25 @@ impl<'a> EnvVariables<'a> {
         self.0.get(index)
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = &EnvVariable<'a>> {
+        self.0.iter()
+    }
+
     pub fn count(&self) -> usize {
         self.0.len()
     }
 
+    /// Encoded length, including the 4 bytes list terminator.
     pub fn len(&self) -> usize {
-        self.0.iter().map(|env_variable| env_variable.len()).sum()
+        self.0
+            .iter()
+            .map(|env_variable| env_variable.len())
+            .sum::<usize>()
+            + 4
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 }
 
@@ -76,11 +107,11 @@ impl<'a> Default for EnvVariables<'a> {
     }
 }
 
-impl<'a> Into<Bytes> for EnvVariables<'a> {
-    fn into(self) -> Bytes {
-        let mut buffer = BytesMut::with_capacity(self.len() + 4); // +4 bytes for null terminator.
+impl<'a> From<EnvVariables<'a>> for Bytes {
+    fn from(value: EnvVariables<'a>) -> Self {
+        let mut buffer = BytesMut::with_capacity(value.len());
 
-        for env_variable in self.0 {
+        for env_variable in value.0 {
             buffer.put::<Bytes>(env_variable.into());
         }
 
@@ -141,17 +172,26 @@ impl<'a> RequiredEnvVariables<'a> {
     }
 
     pub fn count(&self) -> usize {
-        self.script_filename.map_or(0, |_| 1)
-            + self.script_name.map_or(0, |_| 1)
-            + self.query_string.map_or(0, |_| 1)
-            + self.request_method.map_or(0, |_| 1)
+        self.iter().count()
     }
 
     pub fn len(&self) -> usize {
-        self.script_filename.map_or(0, |value| value.len())
-            + self.script_name.map_or(0, |value| value.len())
-            + self.query_string.map_or(0, |value| value.len())
-            + self.request_method.map_or(0, |value| value.len())
+        self.iter().map(|value| value.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = EnvVariable<'a>> {
+        [
+            self.script_filename,
+            self.script_name,
+            self.query_string,
+            self.request_method,
+        ]
+        .into_iter()
+        .flatten()
     }
 }
 
@@ -160,3 +200,55 @@ impl<'a> Default for RequiredEnvVariables<'a> {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_env_variables() {
+        let env_variable = EnvVariable::new("HTTPS", "on");
+        let bytes = env_variable.into_bytes();
+
+        assert_eq!(&bytes[..], b"\x00\x06\x00\x03HTTPS\x00on\x00");
+        assert_eq!(bytes.len(), env_variable.len());
+        assert_eq!(&bytes[env_variable.value_offset()..], b"on\x00");
+    }
+
+    #[test]
+    fn ends_env_variable_lists() {
+        let mut env_variables = EnvVariables::default();
+        env_variables.add("A", "1");
+        env_variables.add("BC", "");
+
+        let length = env_variables.len();
+        let bytes = Bytes::from(env_variables);
+
+        assert_eq!(
+            &bytes[..],
+            b"\x00\x02\x00\x02A\x001\x00\x00\x03\x00\x01BC\x00\x00\x00\x00\x00\x00"
+        );
+        assert_eq!(bytes.len(), length);
+        assert_eq!(&Bytes::from(EnvVariables::default())[..], &[0; 4]);
+    }
+
+    #[test]
+    fn keeps_required_env_variables_in_order() {
+        let mut env_variables = RequiredEnvVariables::new();
+        env_variables.request_method("GET");
+        env_variables.script_filename("/index.php");
+
+        let names: Vec<&str> = env_variables
+            .iter()
+            .map(|variable| variable.name())
+            .collect();
+
+        assert_eq!(names, ["SCRIPT_FILENAME", "REQUEST_METHOD"]);
+        assert_eq!(env_variables.count(), 2);
+        assert_eq!(
+            env_variables.len(),
+            EnvVariable::new("SCRIPT_FILENAME", "/index.php").len()
+                + EnvVariable::new("REQUEST_METHOD", "GET").len()
+        );
+    }
+}
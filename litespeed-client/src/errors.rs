@@ -1,3 +1,4 @@
+use std::io;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -6,6 +7,8 @@ pub enum PacketHeaderError {
     UnknownPacketType,
     #[error("The value provided does not represent an endian.")]
     InvalidEndianness,
+    #[error("The packet does not start with the LiteSpeed signature.")]
+    InvalidVersion,
 }
 
 #[derive(Debug, Error)]
@@ -15,7 +18,21 @@ pub enum HttpHeaderError {
 }
 
 #[derive(Debug, Error)]
-pub enum CommonEnvVariableError{
+pub enum CommonEnvVariableError {
     #[error("The environment variable provided is unknown.")]
     UnknownEnvVariable,
 }
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Failed to communicate with the LiteSpeed process: {0}")]
+    Io(#[from] io::Error),
+    #[error("Received an invalid packet header: {0}")]
+    PacketHeader(#[from] PacketHeaderError),
+    #[error("Received an unexpected {0:?} packet.")]
+    UnexpectedPacket(crate::PacketType),
+    #[error("The response header packet is malformed.")]
+    MalformedResponseHeader,
+    #[error("The connection was closed before the response ended.")]
+    ConnectionClosed,
+}
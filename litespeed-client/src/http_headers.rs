@@ -1,4 +1,6 @@
-use crate::errors::HttpHeaderError;
+use crate::{errors::HttpHeaderError, Endianness};
+use bytes::{BufMut, BytesMut};
+use std::mem::size_of;
 
 #[repr(u8)]
 #[derive(Clone, Debug, Copy)]
@@ -138,12 +140,18 @@ impl From<HttpHeader> for u8 {
     }
 }
 
+#[derive(Clone, Debug, Copy)]
 pub struct CommonHttpHeadersIndex {
     header_length: [u16; HttpHeader::VARIANTS_COUNT],
     header_offset: [u32; HttpHeader::VARIANTS_COUNT],
 }
 
 impl CommonHttpHeadersIndex {
+    // Lengths and offsets, plus 2 bytes of padding to align the offsets.
+    pub const LEN: usize = size_of::<u16>() * HttpHeader::VARIANTS_COUNT
+        + 2
+        + size_of::<u32>() * HttpHeader::VARIANTS_COUNT;
+
     pub fn new() -> Self {
         Self {
             header_length: [0; HttpHeader::VARIANTS_COUNT],
@@ -156,21 +164,55 @@ impl CommonHttpHeadersIndex {
         self.header_length[index] = length;
         self.header_offset[index] = offset;
     }
+
+    pub fn write(&self, buffer: &mut BytesMut, endianness: Endianness) {
+        for length in self.header_length {
+            match endianness {
+                Endianness::LittleEndian => buffer.put_u16_le(length),
+                Endianness::BigEndian => buffer.put_u16(length),
+            }
+        }
+
+        buffer.put_bytes(0, 2);
+
+        for offset in self.header_offset {
+            endianness.put_u32(buffer, offset);
+        }
+    }
+}
+
+impl Default for CommonHttpHeadersIndex {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-// pub struct UnknownHttpHeader {
-//     name_offset: u32,
-//     name_length: u32,
-//     value_offset: u32,
-//     value_length: u32,
-// }
-
-// pub struct ResponseInfo {
-//     headers_count: u32,
-//     status: u32,
-// }
-
-// pub struct ResponseHeader {
-//     packet_header: PacketHeader,
-//     response_info: ResponseInfo,
-// }
+/// Offsets of a header not covered by [`HttpHeader`], relative to the start
+/// of the HTTP headers block.
+#[derive(Clone, Debug, Copy)]
+pub struct UnknownHttpHeader {
+    name_offset: u32,
+    name_length: u32,
+    value_offset: u32,
+    value_length: u32,
+}
+
+impl UnknownHttpHeader {
+    pub const LEN: usize = size_of::<u32>() * 4;
+
+    pub fn new(name_offset: u32, name_length: u32, value_offset: u32, value_length: u32) -> Self {
+        Self {
+            name_offset,
+            name_length,
+            value_offset,
+            value_length,
+        }
+    }
+
+    pub fn write(&self, buffer: &mut BytesMut, endianness: Endianness) {
+        endianness.put_u32(buffer, self.name_offset);
+        endianness.put_u32(buffer, self.name_length);
+        endianness.put_u32(buffer, self.value_offset);
+        endianness.put_u32(buffer, self.value_length);
+    }
+}
@@ -5,6 +5,7 @@ pub mod http_headers;
 pub mod packet_header;
 pub mod request;
 pub mod request_header;
+pub mod response;
 pub mod statics;
 
 pub use client::Client;
@@ -14,3 +15,4 @@ pub use http_headers::*;
 pub use packet_header::*;
 pub use request::Request;
 pub use request_header::*;
+pub use response::Response;
@@ -70,6 +70,35 @@ impl TryFrom<u8> for Endianness {
     }
 }
 
+impl Endianness {
+    pub fn put_u32(&self, buffer: &mut BytesMut, value: u32) {
+        match self {
+            Self::LittleEndian => buffer.put_u32_le(value),
+            Self::BigEndian => buffer.put_u32(value),
+        }
+    }
+
+    pub fn read_u32(&self, bytes: [u8; 4]) -> u32 {
+        match self {
+            Self::LittleEndian => u32::from_le_bytes(bytes),
+            Self::BigEndian => u32::from_be_bytes(bytes),
+        }
+    }
+
+    pub fn read_u16(&self, bytes: [u8; 2]) -> u16 {
+        match self {
+            Self::LittleEndian => u16::from_le_bytes(bytes),
+            Self::BigEndian => u16::from_be_bytes(bytes),
+        }
+    }
+}
+
+impl Default for Endianness {
+    fn default() -> Self {
+        Self::try_from(*ENDIAN).unwrap_or(Self::LittleEndian)
+    }
+}
+
 impl From<Endianness> for u8 {
     fn from(value: Endianness) -> Self {
         match value {
@@ -130,8 +159,47 @@ impl PacketHeader {
         self
     }
 
+    pub fn get_packet_type(&self) -> PacketType {
+        self.packet_type
+    }
+
+    pub fn get_endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    pub fn get_packet_length(&self) -> u32 {
+        self.packet_length
+    }
+
     pub fn len(&self) -> usize {
-        size_of::<u8>() * 4 + size_of::<u32>()
+        Self::LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+impl PacketHeader {
+    pub const LEN: usize = size_of::<u8>() * 4 + size_of::<u32>();
+
+    /// Parses a packet header received from the LiteSpeed process.
+    pub fn from_bytes(bytes: [u8; Self::LEN]) -> Result<Self, PacketHeaderError> {
+        if bytes[0] != b'L' || bytes[1] != b'S' {
+            return Err(PacketHeaderError::InvalidVersion);
+        }
+
+        let packet_type = PacketType::try_from(bytes[2])?;
+        let endianness = Endianness::try_from(bytes[3])?;
+        let length = [bytes[4], bytes[5], bytes[6], bytes[7]];
+
+        Ok(Self::new(
+            bytes[0],
+            bytes[1],
+            packet_type,
+            endianness,
+            endianness.read_u32(length),
+        ))
     }
 }
 
@@ -141,22 +209,69 @@ impl Default for PacketHeader {
             b'L',
             b'S',
             PacketType::BeginRequest,
-            Endianness::try_from(*ENDIAN).unwrap_or(Endianness::LittleEndian),
-            8,
+            Endianness::default(),
+            Self::LEN as u32,
         )
     }
 }
 
-impl Into<Bytes> for PacketHeader {
-    fn into(self) -> Bytes {
-        let mut buffer = BytesMut::with_capacity(self.len());
+impl From<PacketHeader> for Bytes {
+    fn from(value: PacketHeader) -> Self {
+        let mut buffer = BytesMut::with_capacity(value.len());
 
-        buffer.put_u8(self.version_b0);
-        buffer.put_u8(self.version_b1);
-        buffer.put_u8(self.packet_type.into());
-        buffer.put_u8(self.endianness.into());
-        buffer.put_u32(self.packet_length);
+        buffer.put_u8(value.version_b0);
+        buffer.put_u8(value.version_b1);
+        buffer.put_u8(value.packet_type.into());
+        buffer.put_u8(value.endianness.into());
+        value.endianness.put_u32(&mut buffer, value.packet_length);
 
         buffer.into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_and_reads_packet_headers() {
+        let mut header = PacketHeader::default();
+        header.packet_type(PacketType::ResponseHeader);
+        header.endianness(Endianness::BigEndian);
+        header.packet_length(0x0102);
+
+        let bytes = Bytes::from(header);
+
+        assert_eq!(&bytes[..], b"LS\x03\x01\x00\x00\x01\x02");
+
+        let header = PacketHeader::from_bytes(bytes[..].try_into().unwrap()).unwrap();
+
+        assert!(matches!(
+            header.get_packet_type(),
+            PacketType::ResponseHeader
+        ));
+        assert!(matches!(header.get_endianness(), Endianness::BigEndian));
+        assert_eq!(header.get_packet_length(), 0x0102);
+
+        let header = PacketHeader::from_bytes(*b"LS\x05\x00\x08\x00\x00\x00").unwrap();
+
+        assert!(matches!(header.get_packet_type(), PacketType::ResponseEnd));
+        assert_eq!(header.get_packet_length(), 8);
+    }
+
+    #[test]
+    fn rejects_invalid_packet_headers() {
+        assert!(matches!(
+            PacketHeader::from_bytes(*b"FC\x05\x00\x08\x00\x00\x00"),
+            Err(PacketHeaderError::InvalidVersion)
+        ));
+        assert!(matches!(
+            PacketHeader::from_bytes(*b"LS\x0a\x00\x08\x00\x00\x00"),
+            Err(PacketHeaderError::UnknownPacketType)
+        ));
+        assert!(matches!(
+            PacketHeader::from_bytes(*b"LS\x05\x02\x08\x00\x00\x00"),
+            Err(PacketHeaderError::InvalidEndianness)
+        ));
+    }
+}
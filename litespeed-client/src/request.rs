@@ -1,5 +1,8 @@
-use crate::{EnvVariables, PacketHeader, RequestHeader, RequiredEnvVariables};
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use crate::{
+    CommonHttpHeadersIndex, EnvVariables, HttpHeader, PacketHeader, RequestHeader,
+    RequiredEnvVariables, UnknownHttpHeader,
+};
+use bytes::{BufMut, Bytes, BytesMut};
 
 #[derive(Clone, Debug)]
 pub struct Request<'a> {
@@ -8,6 +11,7 @@ pub struct Request<'a> {
     special_env_variables: EnvVariables<'a>,
     required_env_variables: RequiredEnvVariables<'a>,
     general_env_variables: EnvVariables<'a>,
+    http_headers: Vec<(&'a str, &'a str)>,
 }
 
 impl<'a> Request<'a> {
@@ -18,6 +22,7 @@ impl<'a> Request<'a> {
             special_env_variables: EnvVariables::default(),
             required_env_variables: RequiredEnvVariables::default(),
             general_env_variables: EnvVariables::default(),
+            http_headers: Vec::new(),
         }
     }
 
@@ -107,15 +112,32 @@ impl<'a> Request<'a> {
         self
     }
 
-    pub fn len(&self) -> usize {
-        let length = self.packet_header.len()
-            + self.request_header.len()
-            + self.special_env_variables.len()
-            + self.general_env_variables.len();
+    /// Adds any environment variable not covered by the other setters.
+    pub fn env_variable(mut self, name: &'a str, value: &'a str) -> Self {
+        self.general_env_variables.add(name, value);
+        self
+    }
+
+    /// Adds an HTTP request header. Names are matched case insensitively
+    /// against the headers known by the LiteSpeed protocol.
+    pub fn http_header(mut self, name: &'a str, value: &'a str) -> Self {
+        self.http_headers.push((name, value));
+        self
+    }
 
-        let padding = (8 - (length % 8)) % 8;
+    /// Length of the body sent right after the request packet.
+    pub fn body_length(mut self, length: u32) -> Self {
+        self.request_header.request_body_length(length);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        let length = self.env_end() + self.headers_len();
+        length + padding(length)
+    }
 
-        length + padding
+    pub fn is_empty(&self) -> bool {
+        false
     }
 
     pub fn into_bytes(self) -> Bytes {
@@ -123,74 +145,262 @@ impl<'a> Request<'a> {
     }
 }
 
-impl<'a> Into<Bytes> for Request<'a> {
-    fn into(mut self) -> Bytes {
-        let mut packet_header_buffer = BytesMut::with_capacity(self.packet_header.len());
-        let mut request_header_buffer = BytesMut::with_capacity(self.request_header.len());
+impl<'a> Request<'a> {
+    // Position right after both environment variable lists.
+    fn env_end(&self) -> usize {
+        self.packet_header.len()
+            + self.request_header.len()
+            + self.special_env_variables.len()
+            + self.required_env_variables.len()
+            + self.general_env_variables.len()
+    }
 
-        let mut buffer = BytesMut::with_capacity(
-            self.special_env_variables.len()
-                + self.required_env_variables.len()
-                + self.general_env_variables.len(),
-        );
+    // Header index, unknown header offsets and the raw headers block, starting
+    // at the 8 bytes aligned position following the environment variables.
+    fn headers_len(&self) -> usize {
+        let unknown_headers = self
+            .http_headers
+            .iter()
+            .filter(|(name, _)| common_header(name).is_none())
+            .count();
+
+        padding(self.env_end())
+            + CommonHttpHeadersIndex::LEN
+            + UnknownHttpHeader::LEN * unknown_headers
+            + self
+                .http_headers
+                .iter()
+                .map(|(name, value)| name.len() + value.len() + 4)
+                .sum::<usize>()
+    }
+}
+
+impl<'a> Default for Request<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> From<Request<'a>> for Bytes {
+    fn from(mut value: Request<'a>) -> Self {
+        let length = value.len();
+        let endianness = value.packet_header.get_endianness();
+        let mut buffer = BytesMut::with_capacity(length);
 
         // Update the packet length
-        self.packet_header.packet_length(self.len() as u32);
+        value.packet_header.packet_length(length as u32);
 
         // Update the number of environment variables
-        self.request_header.env_variables_count(
-            (self.required_env_variables.count() + self.general_env_variables.count()) as u32,
+        value
+            .request_header
+            .special_env_variables_count(value.special_env_variables.count() as u32);
+        value.request_header.env_variables_count(
+            (value.required_env_variables.count() + value.general_env_variables.count()) as u32,
         );
 
-        // Update script filename offset and append to buffer
-        if let Some(script_filename) = self.required_env_variables.get_script_filename() {
-            self.request_header.script_filename_offset(
-                (self.packet_header.len() + self.request_header.len() + buffer.len()) as u32,
-            );
-            buffer.put::<Bytes>(script_filename.into());
+        // Required variables go first in the environment list, and the request
+        // header points to their values.
+        let mut offset = value.packet_header.len()
+            + value.request_header.len()
+            + value.special_env_variables.len();
+
+        for env_variable in value.required_env_variables.iter() {
+            let value_offset = (offset + env_variable.value_offset()) as u32;
+
+            match env_variable.name() {
+                "SCRIPT_FILENAME" => value.request_header.script_filename_offset(value_offset),
+                "SCRIPT_NAME" => value.request_header.script_name_offset(value_offset),
+                "QUERY_STRING" => value.request_header.query_string_offset(value_offset),
+                _ => value.request_header.request_method_offset(value_offset),
+            };
+
+            offset += env_variable.len();
+        }
+
+        // Build the HTTP headers block and its index.
+        let mut index = CommonHttpHeadersIndex::new();
+        let mut unknown_headers = Vec::new();
+        let mut headers = BytesMut::new();
+
+        for (name, header_value) in &value.http_headers {
+            let name_offset = headers.len() as u32;
+            headers.extend_from_slice(name.as_bytes());
+            headers.extend_from_slice(b": ");
+            let value_offset = headers.len() as u32;
+            headers.extend_from_slice(header_value.as_bytes());
+            headers.extend_from_slice(b"\r\n");
+
+            match common_header(name) {
+                Some(header) => index.set_header(header, header_value.len() as u16, value_offset),
+                None => unknown_headers.push(UnknownHttpHeader::new(
+                    name_offset,
+                    name.len() as u32,
+                    value_offset,
+                    header_value.len() as u32,
+                )),
+            }
+        }
+
+        headers.put_bytes(0, padding(value.env_end() + value.headers_len()));
+
+        value
+            .request_header
+            .unknown_headers_count(unknown_headers.len() as u32);
+        value
+            .request_header
+            .http_header_length(headers.len() as u32);
+
+        // Append packet header and request header to buffer
+        buffer.put::<Bytes>(value.packet_header.into());
+        value.request_header.write(&mut buffer, endianness);
+
+        // Append environment variables to buffer
+        buffer.put::<Bytes>(value.special_env_variables.into());
+
+        for env_variable in value.required_env_variables.iter() {
+            buffer.put::<Bytes>(env_variable.into());
         }
 
-        // Update script name offset and append to buffer
-        if let Some(script_name) = self.required_env_variables.get_script_name() {
-            self.request_header.script_name_offset(
-                (self.packet_header.len() + self.request_header.len() + buffer.len()) as u32,
-            );
-            buffer.put::<Bytes>(script_name.into());
+        buffer.put::<Bytes>(value.general_env_variables.into());
+
+        // Align the header index to 8 bytes
+        buffer.put_bytes(0, padding(buffer.len()));
+
+        // Append HTTP headers to buffer
+        index.write(&mut buffer, endianness);
+
+        for unknown_header in unknown_headers {
+            unknown_header.write(&mut buffer, endianness);
         }
 
-        // Update query string offset and append to buffer
-        if let Some(query_string) = self.required_env_variables.get_query_string() {
-            self.request_header.query_string_offset(
-                (self.packet_header.len() + self.request_header.len() + buffer.len()) as u32,
-            );
-            buffer.put::<Bytes>(query_string.into());
+        buffer.put(headers);
+
+        buffer.into()
+    }
+}
+
+fn common_header(name: &str) -> Option<HttpHeader> {
+    HttpHeader::try_from(name.to_ascii_lowercase().as_str()).ok()
+}
+
+fn padding(length: usize) -> usize {
+    (8 - (length % 8)) % 8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Endianness;
+
+    // Reads the encoded request the way the LiteSpeed process does.
+    struct Packet(Bytes);
+
+    impl Packet {
+        fn u16(&self, offset: usize) -> u16 {
+            Endianness::default().read_u16(self.0[offset..offset + 2].try_into().unwrap())
+        }
+
+        fn u32(&self, offset: usize) -> u32 {
+            Endianness::default().read_u32(self.0[offset..offset + 4].try_into().unwrap())
+        }
+
+        // Field of the request header, by position.
+        fn field(&self, index: usize) -> u32 {
+            self.u32(PacketHeader::LEN + index * 4)
         }
 
-        // Update request method offset and append to buffer
-        if let Some(request_method) = self.required_env_variables.get_request_method() {
-            self.request_header.request_method_offset(
-                (self.packet_header.len() + self.request_header.len() + buffer.len()) as u32,
-            );
-            buffer.put::<Bytes>(request_method.into());
+        // Null terminated string at `offset`.
+        fn string(&self, offset: u32) -> &str {
+            let bytes = &self.0[offset as usize..];
+            let end = bytes.iter().position(|byte| *byte == 0).unwrap();
+
+            std::str::from_utf8(&bytes[..end]).unwrap()
         }
+    }
 
-        // Add padding
-        let buffer_length = self.packet_header.len() + self.request_header.len() + buffer.len();
-        let padding = (8 - (buffer_length % 8)) % 8;
-        buffer.put_bytes(0, padding);
+    fn request() -> Packet {
+        let request = Request::new()
+            .request_method("POST")
+            .script_filename("/mnt/wordpress/index.php")
+            .script_name("/index.php")
+            .query_string("p=1")
+            .document_root("/mnt/wordpress")
+            .http_header("Host", "example.com")
+            .http_header("X-Sigan-Test", "yes")
+            .body_length(5);
+
+        Packet(request.into_bytes())
+    }
 
-        // Append packet header to buffer
-        packet_header_buffer.put::<Bytes>(self.packet_header.into());
+    #[test]
+    fn writes_the_packet_and_request_headers() {
+        let packet = request();
+        let bytes = &packet.0;
+
+        assert_eq!(&bytes[..3], b"LS\x01");
+        assert_eq!(packet.u32(4) as usize, bytes.len());
+        assert_eq!(bytes.len() % 8, 0);
+
+        // Body length, unknown headers, environment variables and special
+        // environment variables.
+        assert_eq!(packet.field(1), 5);
+        assert_eq!(packet.field(6), 1);
+        assert_eq!(packet.field(7), 5);
+        assert_eq!(packet.field(8), 0);
+    }
+
+    #[test]
+    fn points_to_the_required_env_variables() {
+        let packet = request();
+
+        assert_eq!(packet.string(packet.field(2)), "/mnt/wordpress/index.php");
+        assert_eq!(packet.string(packet.field(3)), "/index.php");
+        assert_eq!(packet.string(packet.field(4)), "p=1");
+        assert_eq!(packet.string(packet.field(5)), "POST");
+
+        // Required variables come first in the environment list, after the
+        // empty special list.
+        let list = PacketHeader::LEN + RequestHeader::LEN + 4;
+
+        // Their lengths are big endian whatever the packet endianness.
+        assert_eq!(&packet.0[list..list + 4], b"\x00\x10\x00\x19");
+        assert_eq!(packet.string(list as u32 + 4), "SCRIPT_FILENAME");
+        assert!(packet
+            .0
+            .windows(15)
+            .any(|window| window == b"DOCUMENT_ROOT\0/"));
+    }
+
+    #[test]
+    fn indexes_the_http_headers() {
+        let packet = request();
+        let bytes = &packet.0;
+
+        let headers_length = packet.field(0) as usize;
+        let headers = bytes.len() - headers_length;
+        let unknown_headers = headers - UnknownHttpHeader::LEN;
+        let index = unknown_headers - CommonHttpHeadersIndex::LEN;
+
+        assert_eq!(index % 8, 0);
+        assert_eq!(
+            &bytes[headers..],
+            b"Host: example.com\r\nX-Sigan-Test: yes\r\n\0\0"
+        );
 
-        // Append request header to buffer
-        request_header_buffer.put::<Bytes>(self.request_header.into());
+        // `Host` is a common header, found by its position in the index.
+        let host = HttpHeader::Host as usize;
+        let length = packet.u16(index + host * 2) as usize;
+        let offset = packet.u32(index + HttpHeader::VARIANTS_COUNT * 2 + 2 + host * 4) as usize;
 
-        let mut chain = packet_header_buffer
-            .chain(request_header_buffer)
-            .chain(buffer);
+        assert_eq!(&bytes[headers + offset..][..length], b"example.com");
 
-        // HTTP headers
+        // Other headers are listed with the offsets of their name and value.
+        let name = packet.u32(unknown_headers) as usize;
+        let name_length = packet.u32(unknown_headers + 4) as usize;
+        let value = packet.u32(unknown_headers + 8) as usize;
+        let value_length = packet.u32(unknown_headers + 12) as usize;
 
-        chain.copy_to_bytes(self.len())
+        assert_eq!(&bytes[headers + name..][..name_length], b"X-Sigan-Test");
+        assert_eq!(&bytes[headers + value..][..value_length], b"yes");
     }
 }
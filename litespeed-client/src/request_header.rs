@@ -1,4 +1,5 @@
-use bytes::{BufMut, Bytes, BytesMut};
+use crate::Endianness;
+use bytes::{Bytes, BytesMut};
 use std::mem::size_of;
 
 #[derive(Clone, Debug, Copy)]
@@ -91,12 +92,32 @@ impl RequestHeader {
     }
 
     pub fn len(&self) -> usize {
-        size_of::<u32>() * 9
+        Self::LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
     }
 
     pub fn into_bytes(self) -> Bytes {
         self.into()
     }
+
+    pub fn write(&self, buffer: &mut BytesMut, endianness: Endianness) {
+        endianness.put_u32(buffer, self.http_header_length);
+        endianness.put_u32(buffer, self.request_body_length);
+        endianness.put_u32(buffer, self.script_filename_offset);
+        endianness.put_u32(buffer, self.script_name_offset);
+        endianness.put_u32(buffer, self.query_string_offset);
+        endianness.put_u32(buffer, self.request_method_offset);
+        endianness.put_u32(buffer, self.unknown_headers_count);
+        endianness.put_u32(buffer, self.env_variables_count);
+        endianness.put_u32(buffer, self.special_env_variables_count);
+    }
+}
+
+impl RequestHeader {
+    pub const LEN: usize = size_of::<u32>() * 9;
 }
 
 impl Default for RequestHeader {
@@ -105,19 +126,11 @@ impl Default for RequestHeader {
     }
 }
 
-impl Into<Bytes> for RequestHeader {
-    fn into(self) -> Bytes {
-        let mut buffer = BytesMut::with_capacity(self.len());
-
-        buffer.put_u32(self.http_header_length);
-        buffer.put_u32(self.request_body_length);
-        buffer.put_u32(self.script_filename_offset);
-        buffer.put_u32(self.script_name_offset);
-        buffer.put_u32(self.query_string_offset);
-        buffer.put_u32(self.request_method_offset);
-        buffer.put_u32(self.unknown_headers_count);
-        buffer.put_u32(self.env_variables_count);
-        buffer.put_u32(self.special_env_variables_count);
+impl From<RequestHeader> for Bytes {
+    fn from(value: RequestHeader) -> Self {
+        let mut buffer = BytesMut::with_capacity(value.len());
+
+        value.write(&mut buffer, Endianness::default());
 
         buffer.into()
     }
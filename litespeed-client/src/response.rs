@@ -0,0 +1,224 @@
+use crate::{errors::ClientError, Endianness, PacketHeader, PacketType};
+use std::mem::size_of;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+#[derive(Clone, Debug, Default)]
+pub struct Response {
+    status: u16,
+    headers: Vec<String>,
+    body: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+impl Response {
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Raw `Name: value` header lines, in the order PHP sent them.
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn stderr(&self) -> &[u8] {
+        &self.stderr
+    }
+
+    pub fn into_parts(self) -> (u16, Vec<String>, Vec<u8>, Vec<u8>) {
+        (self.status, self.headers, self.body, self.stderr)
+    }
+
+    /// Reads packets from the stream until the end of the response.
+    pub async fn read_from<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Self, ClientError> {
+        let mut response = Self::default();
+
+        loop {
+            let mut header = [0; PacketHeader::LEN];
+
+            stream
+                .read_exact(&mut header)
+                .await
+                .map_err(|error| match error.kind() {
+                    std::io::ErrorKind::UnexpectedEof => ClientError::ConnectionClosed,
+                    _ => ClientError::Io(error),
+                })?;
+
+            let header = PacketHeader::from_bytes(header)?;
+            let length = (header.get_packet_length() as usize).saturating_sub(PacketHeader::LEN);
+
+            let mut payload = vec![0; length];
+            stream.read_exact(&mut payload).await?;
+
+            match header.get_packet_type() {
+                PacketType::ResponseHeader => {
+                    response.read_headers(&payload, header.get_endianness())?
+                }
+                PacketType::ResponseStream => response.body.extend_from_slice(&payload),
+                PacketType::StderrStream => response.stderr.extend_from_slice(&payload),
+                PacketType::RequestReceived => {}
+                PacketType::ResponseEnd => break,
+                packet_type => return Err(ClientError::UnexpectedPacket(packet_type)),
+            }
+        }
+
+        Ok(response)
+    }
+
+    // Payload layout: headers count, status, one length per header and then
+    // the null terminated headers.
+    fn read_headers(&mut self, payload: &[u8], endianness: Endianness) -> Result<(), ClientError> {
+        let read_u32 = |offset: usize| -> Result<u32, ClientError> {
+            payload
+                .get(offset..offset + size_of::<u32>())
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(|bytes| endianness.read_u32(bytes))
+                .ok_or(ClientError::MalformedResponseHeader)
+        };
+
+        let count = read_u32(0)? as usize;
+        self.status = read_u32(size_of::<u32>())? as u16;
+
+        let lengths_offset = size_of::<u32>() * 2;
+        let mut offset = lengths_offset + count * size_of::<u16>();
+
+        for index in 0..count {
+            let length_offset = lengths_offset + index * size_of::<u16>();
+            let length = payload
+                .get(length_offset..length_offset + size_of::<u16>())
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(|bytes| endianness.read_u16(bytes) as usize)
+                .ok_or(ClientError::MalformedResponseHeader)?;
+
+            let header = payload
+                .get(offset..offset + length)
+                .ok_or(ClientError::MalformedResponseHeader)?;
+
+            let header = header.strip_suffix(&[0]).unwrap_or(header);
+            self.headers
+                .push(String::from_utf8_lossy(header).into_owned());
+
+            offset += length;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PacketHeaderError;
+    use bytes::{BufMut, Bytes, BytesMut};
+    use tokio::io::AsyncWriteExt;
+
+    fn packet(packet_type: PacketType, payload: &[u8]) -> Vec<u8> {
+        let mut header = PacketHeader::default();
+        header.packet_type(packet_type);
+        header.packet_length((PacketHeader::LEN + payload.len()) as u32);
+
+        [&Bytes::from(header)[..], payload].concat()
+    }
+
+    // Headers count, status, lengths and null terminated headers.
+    fn response_header(status: u32, headers: &[&str]) -> Vec<u8> {
+        let endianness = Endianness::default();
+        let mut payload = BytesMut::new();
+
+        endianness.put_u32(&mut payload, headers.len() as u32);
+        endianness.put_u32(&mut payload, status);
+
+        for header in headers {
+            let length = header.len() as u16 + 1;
+
+            match endianness {
+                Endianness::LittleEndian => payload.put_u16_le(length),
+                Endianness::BigEndian => payload.put_u16(length),
+            }
+        }
+
+        for header in headers {
+            payload.extend_from_slice(header.as_bytes());
+            payload.put_u8(0);
+        }
+
+        payload.to_vec()
+    }
+
+    fn response() -> Vec<u8> {
+        [
+            packet(PacketType::RequestReceived, b""),
+            packet(
+                PacketType::ResponseHeader,
+                &response_header(201, &["Content-Type: text/html", "Set-Cookie: a=1"]),
+            ),
+            packet(PacketType::ResponseStream, b"<p>Sa"),
+            packet(PacketType::StderrStream, b"PHP Notice:  Undefined index"),
+            packet(PacketType::ResponseStream, b"ved</p>"),
+            packet(PacketType::ResponseEnd, b""),
+        ]
+        .concat()
+    }
+
+    fn assert_response(response: Response) {
+        assert_eq!(response.status(), 201);
+        assert_eq!(
+            response.headers(),
+            ["Content-Type: text/html", "Set-Cookie: a=1"]
+        );
+        assert_eq!(response.body(), b"<p>Saved</p>");
+        assert_eq!(response.stderr(), b"PHP Notice:  Undefined index");
+    }
+
+    #[tokio::test]
+    async fn reads_responses() {
+        let bytes = response();
+
+        assert_response(Response::read_from(&mut &bytes[..]).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn reads_responses_split_across_reads() {
+        // Writes go through 3 bytes at a time, splitting packet headers too.
+        let (mut writer, mut reader) = tokio::io::duplex(3);
+
+        tokio::spawn(async move { writer.write_all(&response()).await });
+
+        assert_response(Response::read_from(&mut reader).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn fails_on_truncated_or_malformed_responses() {
+        let bytes = packet(PacketType::ResponseStream, b"<p>");
+
+        assert!(matches!(
+            Response::read_from(&mut &bytes[..]).await,
+            Err(ClientError::ConnectionClosed)
+        ));
+
+        // Two headers announced, a single one sent.
+        let mut payload = response_header(200, &["A: 1", "B: 2"]);
+        payload.truncate(payload.len() - 5);
+        let bytes = packet(PacketType::ResponseHeader, &payload);
+
+        assert!(matches!(
+            Response::read_from(&mut &bytes[..]).await,
+            Err(ClientError::MalformedResponseHeader)
+        ));
+
+        let bytes = packet(PacketType::BeginRequest, b"");
+
+        assert!(matches!(
+            Response::read_from(&mut &bytes[..]).await,
+            Err(ClientError::UnexpectedPacket(PacketType::BeginRequest))
+        ));
+
+        assert!(matches!(
+            Response::read_from(&mut &b"HTTP/1.1 200 OK\r\n"[..]).await,
+            Err(ClientError::PacketHeader(PacketHeaderError::InvalidVersion))
+        ));
+    }
+}
@@ -8,10 +8,12 @@ fn main() {
 
     // Prevents generating bindings outside Docker.
     if env::var("RUNNING_IN_DOCKER").is_ok() {
-        generate_bindings(&output_file);
+        generate_bindings(output_file);
     }
 
     // Sets a cfg variable to prevent including bindings file if it doesn't exist.
+    println!("cargo::rustc-check-cfg=cfg(include_bindings)");
+
     if output_file.exists() {
         println!("cargo:rustc-cfg=include_bindings")
     }
@@ -1,6 +1,8 @@
 #![allow(non_upper_case_globals)]
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
+#![allow(improper_ctypes)]
+#![allow(clippy::all)]
 
 #[cfg(include_bindings)]
 include!("bindings.rs");
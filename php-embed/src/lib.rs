@@ -0,0 +1,65 @@
+mod sapi;
+
+use php_embed_sys::{
+    sigan_zend_string_init, sigan_zend_string_release, zend_destroy_file_handle, zend_file_handle,
+    zend_stream_init_filename, zend_string,
+};
+use std::ffi::CString;
+use std::mem;
+
+pub use sapi::{Interrupt, Php, Request, Response};
+
+pub struct ZString {
+    inner: *mut zend_string,
+}
+
+impl ZString {
+    pub fn new(value: &str) -> Self {
+        unsafe {
+            Self {
+                inner: sigan_zend_string_init(value.as_ptr().cast(), value.len()),
+            }
+        }
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut zend_string {
+        self.inner
+    }
+}
+
+impl Drop for ZString {
+    fn drop(&mut self) {
+        unsafe { sigan_zend_string_release(self.inner) }
+    }
+}
+
+pub struct ZFileHandle {
+    // Boxed so the handle keeps its address, Zend stores pointers to it.
+    inner: Box<zend_file_handle>,
+    // Zend keeps a pointer to the filename until the handle is destroyed.
+    _filename: CString,
+}
+
+impl ZFileHandle {
+    pub fn new(filename: &str) -> Self {
+        let filename = CString::new(filename).unwrap_or_default();
+        let mut inner: Box<zend_file_handle> = Box::new(unsafe { mem::zeroed() });
+
+        unsafe { zend_stream_init_filename(inner.as_mut(), filename.as_ptr()) };
+
+        Self {
+            inner,
+            _filename: filename,
+        }
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut zend_file_handle {
+        self.inner.as_mut()
+    }
+}
+
+impl Drop for ZFileHandle {
+    fn drop(&mut self) {
+        unsafe { zend_destroy_file_handle(self.inner.as_mut()) }
+    }
+}
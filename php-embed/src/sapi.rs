@@ -0,0 +1,281 @@
+use crate::ZFileHandle;
+use php_embed_sys::{
+    executor_globals, php_embed_init, php_embed_module, php_embed_shutdown, php_execute_script,
+    php_register_variable_safe, php_request_shutdown, php_request_startup, sapi_globals,
+    sapi_header_op_enum, sapi_header_struct, sapi_headers_struct, zend_atomic_bool_store, zval,
+    SAPI_HEADER_ADD, SAPI_HEADER_DO_SEND,
+};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::marker::PhantomData;
+use std::ptr::{self, addr_of_mut};
+use std::slice;
+
+thread_local! {
+    static CONTEXT: RefCell<Option<Context>> = const { RefCell::new(None) };
+}
+
+/// A request for the embedded interpreter.
+#[derive(Clone, Debug, Default)]
+pub struct Request {
+    pub script_filename: String,
+    pub request_method: String,
+    pub request_uri: String,
+    pub query_string: String,
+    pub content_type: Option<String>,
+    pub cookie: Option<String>,
+    pub variables: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// The output of a script, with the headers as PHP sent them.
+#[derive(Clone, Debug, Default)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<String>,
+    pub body: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+// Request strings must outlive the request, PHP keeps pointers to them.
+struct Context {
+    request_method: CString,
+    request_uri: CString,
+    query_string: CString,
+    content_type: Option<CString>,
+    cookie: Option<CString>,
+    variables: Vec<(CString, String)>,
+    body: Vec<u8>,
+    body_read: usize,
+    response: Response,
+}
+
+impl From<Request> for Context {
+    fn from(request: Request) -> Self {
+        let cstring = |value: String| CString::new(value).unwrap_or_default();
+
+        Self {
+            request_method: cstring(request.request_method),
+            request_uri: cstring(request.request_uri),
+            query_string: cstring(request.query_string),
+            content_type: request.content_type.map(cstring),
+            cookie: request.cookie.map(cstring),
+            variables: request
+                .variables
+                .into_iter()
+                .map(|(name, value)| (cstring(name), value))
+                .collect(),
+            body: request.body,
+            body_read: 0,
+            response: Response::default(),
+        }
+    }
+}
+
+/// The embedded PHP interpreter.
+///
+/// PHP is built without thread safety, so the interpreter is bound to the
+/// thread that initialized it and can't be sent to other threads.
+pub struct Php {
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Php {
+    pub fn init() -> Self {
+        unsafe {
+            let module = addr_of_mut!(php_embed_module);
+
+            (*module).ub_write = Some(ub_write);
+            (*module).header_handler = Some(header_handler);
+            (*module).send_headers = Some(send_headers);
+            (*module).send_header = Some(send_header);
+            (*module).read_post = Some(read_post);
+            (*module).read_cookies = Some(read_cookies);
+            (*module).register_server_variables = Some(register_server_variables);
+            (*module).log_message = Some(log_message);
+
+            php_embed_init(0, ptr::null_mut());
+
+            // The embed SAPI starts a request on init, requests are started
+            // manually for every execution instead.
+            php_request_shutdown(ptr::null_mut());
+        }
+
+        Self {
+            _not_send: PhantomData,
+        }
+    }
+
+    /// A handle stopping the script this interpreter runs, from other threads.
+    pub fn interrupt_handle(&self) -> Interrupt {
+        Interrupt { _private: () }
+    }
+
+    pub fn execute(&mut self, request: Request) -> Response {
+        let script_filename = request.script_filename.clone();
+
+        CONTEXT.with_borrow_mut(|context| *context = Some(Context::from(request)));
+
+        unsafe {
+            CONTEXT.with_borrow(|context| {
+                if let Some(context) = context {
+                    let request_info = &mut (*addr_of_mut!(sapi_globals)).request_info;
+
+                    request_info.request_method = context.request_method.as_ptr();
+                    request_info.request_uri = context.request_uri.as_ptr().cast_mut();
+                    request_info.query_string = context.query_string.as_ptr().cast_mut();
+                    request_info.path_translated = ptr::null_mut();
+                    request_info.content_length = context.body.len() as i64;
+                    request_info.content_type = context
+                        .content_type
+                        .as_ref()
+                        .map_or(ptr::null(), |value| value.as_ptr());
+                }
+            });
+
+            if php_request_startup() == 0 {
+                // The handle allocates its filename on the request heap, so
+                // it is created and destroyed within the request, like
+                // `php_cli` does.
+                let mut file_handle = ZFileHandle::new(&script_filename);
+                php_execute_script(file_handle.as_mut_ptr());
+                drop(file_handle);
+            }
+
+            php_request_shutdown(ptr::null_mut());
+        }
+
+        CONTEXT
+            .with_borrow_mut(|context| context.take())
+            .map(|context| context.response)
+            .unwrap_or_default()
+    }
+}
+
+/// Stops the script running on the interpreter thread, from any thread.
+#[derive(Clone, Copy, Debug)]
+pub struct Interrupt {
+    _private: (),
+}
+
+impl Interrupt {
+    /// Makes the running script fail at its next instruction, like when
+    /// `max_execution_time` is exceeded. A script blocked in a call, like a
+    /// database query, stops once the call returns. PHP clears the flags when
+    /// a request starts, so later requests are not affected.
+    pub fn interrupt(&self) {
+        unsafe {
+            let globals = addr_of_mut!(executor_globals);
+
+            zend_atomic_bool_store(addr_of_mut!((*globals).timed_out), true);
+            zend_atomic_bool_store(addr_of_mut!((*globals).vm_interrupt), true);
+        }
+    }
+}
+
+impl Drop for Php {
+    fn drop(&mut self) {
+        unsafe { php_embed_shutdown() }
+    }
+}
+
+unsafe extern "C" fn ub_write(value: *const c_char, length: usize) -> usize {
+    let bytes = slice::from_raw_parts(value.cast::<u8>(), length);
+
+    CONTEXT.with_borrow_mut(|context| {
+        if let Some(context) = context {
+            context.response.body.extend_from_slice(bytes);
+        }
+    });
+
+    length
+}
+
+unsafe extern "C" fn header_handler(
+    _header: *mut sapi_header_struct,
+    _operation: sapi_header_op_enum,
+    _headers: *mut sapi_headers_struct,
+) -> c_int {
+    SAPI_HEADER_ADD as c_int
+}
+
+unsafe extern "C" fn send_headers(headers: *mut sapi_headers_struct) -> c_int {
+    let status = (*headers).http_response_code;
+
+    CONTEXT.with_borrow_mut(|context| {
+        if let Some(context) = context {
+            context.response.status = if status > 0 { status as u16 } else { 200 };
+        }
+    });
+
+    SAPI_HEADER_DO_SEND as c_int
+}
+
+unsafe extern "C" fn send_header(header: *mut sapi_header_struct, _server_context: *mut c_void) {
+    // Called with a null header once all headers were sent.
+    if header.is_null() || (*header).header.is_null() {
+        return;
+    }
+
+    let bytes = slice::from_raw_parts((*header).header.cast::<u8>(), (*header).header_len);
+
+    CONTEXT.with_borrow_mut(|context| {
+        if let Some(context) = context {
+            let header = String::from_utf8_lossy(bytes).into_owned();
+            context.response.headers.push(header);
+        }
+    });
+}
+
+unsafe extern "C" fn read_post(buffer: *mut c_char, count: usize) -> usize {
+    CONTEXT.with_borrow_mut(|context| {
+        let Some(context) = context else {
+            return 0;
+        };
+
+        let remaining = &context.body[context.body_read..];
+        let length = remaining.len().min(count);
+
+        ptr::copy_nonoverlapping(remaining.as_ptr(), buffer.cast::<u8>(), length);
+        context.body_read += length;
+
+        length
+    })
+}
+
+unsafe extern "C" fn read_cookies() -> *mut c_char {
+    CONTEXT.with_borrow(|context| {
+        context
+            .as_ref()
+            .and_then(|context| context.cookie.as_ref())
+            .map_or(ptr::null_mut(), |cookie| cookie.as_ptr().cast_mut())
+    })
+}
+
+unsafe extern "C" fn register_server_variables(track_vars_array: *mut zval) {
+    CONTEXT.with_borrow(|context| {
+        let Some(context) = context else {
+            return;
+        };
+
+        for (name, value) in &context.variables {
+            php_register_variable_safe(
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                track_vars_array,
+            );
+        }
+    });
+}
+
+unsafe extern "C" fn log_message(message: *const c_char, _syslog_type: c_int) {
+    let message = CStr::from_ptr(message).to_bytes();
+
+    CONTEXT.with_borrow_mut(|context| {
+        if let Some(context) = context {
+            context.response.stderr.extend_from_slice(message);
+            context.response.stderr.push(b'\n');
+        }
+    });
+}
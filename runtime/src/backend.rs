@@ -0,0 +1,267 @@
+use crate::cgi::CgiParams;
+use crate::config::RuntimeConfig;
+use crate::context::{RuntimeContext, RuntimeExt};
+use bytes::Bytes;
+use lambda_http::Request;
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+#[cfg(feature = "embed")]
+use crate::embed::EmbedBackend;
+#[cfg(feature = "fastcgi")]
+use crate::fast_cgi::FastCgiBackend;
+#[cfg(feature = "lsapi")]
+use crate::lsapi::LsapiBackend;
+
+#[derive(Debug, Error)]
+pub enum BackendError {
+    #[error("Unknown PHP backend `{0}`, expected one of: {1}")]
+    UnknownBackend(String, String),
+    #[error("The `{0}` PHP backend is not enabled in this build")]
+    DisabledBackend(BackendKind),
+    #[error("Failed to start the PHP process: {0}")]
+    Process(#[source] std::io::Error),
+    #[cfg(any(feature = "lsapi", feature = "fastcgi"))]
+    #[error("Failed to connect to the PHP process: {0}")]
+    Connection(#[source] std::io::Error),
+    #[error("Failed to execute the PHP request: {0}")]
+    Execution(String),
+    #[error("The PHP backend is not running")]
+    Unavailable,
+    #[error("The PHP request ran out of time")]
+    Timeout,
+}
+
+/// A request as handed to PHP: the CGI variables and the request body.
+#[derive(Clone, Debug, Default)]
+pub struct BackendRequest {
+    pub params: CgiParams,
+    pub body: Vec<u8>,
+    /// `None` for requests the runtime makes itself, like warmup scripts.
+    pub context: Option<RuntimeContext>,
+}
+
+impl BackendRequest {
+    /// A request for PHP with the body and the runtime context of `req`.
+    pub fn from_request(req: &Request, params: CgiParams) -> Self {
+        Self {
+            params,
+            body: req.body().to_vec(),
+            context: req.runtime_context().cloned(),
+        }
+    }
+}
+
+/// Raw CGI output of PHP. `stdout` holds the headers block followed by the
+/// body, the same for every backend.
+#[derive(Clone, Debug, Default)]
+pub struct BackendResponse {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// What PHP writes, in streaming mode.
+#[derive(Debug)]
+pub enum Output {
+    /// CGI output, the headers block followed by the body.
+    Stdout(Bytes),
+    /// Error output, so fatal errors are known before the headers go out.
+    Stderr(Bytes),
+}
+
+/// Where PHP output is sent to as it is written, in streaming mode.
+pub type OutputSender = mpsc::Sender<Result<Output, BackendError>>;
+
+/// A way of running PHP scripts.
+pub trait PhpBackend: Sized + Send + Sync {
+    /// Starts PHP, and waits until it is ready to accept requests.
+    fn start(config: &RuntimeConfig) -> impl Future<Output = Result<Self, BackendError>> + Send;
+
+    /// Executes a request and buffers the whole output.
+    fn execute(
+        &self,
+        request: BackendRequest,
+    ) -> impl Future<Output = Result<BackendResponse, BackendError>> + Send;
+
+    /// Executes a request, sending the output to `output` as PHP writes it,
+    /// and returns the error output. Backends that can't stream send it all
+    /// at once, the error output first.
+    fn execute_stream(
+        &self,
+        request: BackendRequest,
+        output: &OutputSender,
+    ) -> impl Future<Output = Result<Vec<u8>, BackendError>> + Send {
+        async move {
+            let response = self.execute(request).await?;
+
+            // The receiver is gone when the client disconnected.
+            if !response.stderr.is_empty() {
+                let stderr = Bytes::copy_from_slice(&response.stderr);
+                let _ = output.send(Ok(Output::Stderr(stderr))).await;
+            }
+
+            let _ = output
+                .send(Ok(Output::Stdout(response.stdout.into())))
+                .await;
+
+            Ok(response.stderr)
+        }
+    }
+
+    /// Checks whether PHP is still able to handle requests.
+    fn health(&self) -> impl Future<Output = Result<(), BackendError>> + Send;
+
+    /// Stops the requests in progress, leaving PHP ready for the next ones.
+    fn abort(&self) -> impl Future<Output = Result<(), BackendError>> + Send;
+
+    /// Stops PHP. Requests executed afterwards fail.
+    fn shutdown(&self) -> impl Future<Output = Result<(), BackendError>> + Send;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    Lsapi,
+    FastCgi,
+    Embed,
+}
+
+impl BackendKind {
+    const ALL: [Self; 3] = [Self::Lsapi, Self::FastCgi, Self::Embed];
+
+    /// The backend used when none is configured.
+    pub fn first_enabled() -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.is_enabled())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            Self::Lsapi => cfg!(feature = "lsapi"),
+            Self::FastCgi => cfg!(feature = "fastcgi"),
+            Self::Embed => cfg!(feature = "embed"),
+        }
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = BackendError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "lsapi" | "litespeed" => Ok(Self::Lsapi),
+            "fastcgi" | "fcgi" => Ok(Self::FastCgi),
+            "embed" => Ok(Self::Embed),
+            _ => Err(BackendError::UnknownBackend(
+                value.into(),
+                Self::ALL.map(|kind| kind.to_string()).join(", "),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lsapi => write!(f, "lsapi"),
+            Self::FastCgi => write!(f, "fastcgi"),
+            Self::Embed => write!(f, "embed"),
+        }
+    }
+}
+
+/// The backend selected at startup.
+pub enum Backend {
+    #[cfg(feature = "lsapi")]
+    Lsapi(LsapiBackend),
+    #[cfg(feature = "fastcgi")]
+    FastCgi(FastCgiBackend),
+    #[cfg(feature = "embed")]
+    Embed(EmbedBackend),
+}
+
+impl Backend {
+    pub async fn start(config: &RuntimeConfig) -> Result<Self, BackendError> {
+        match config.php_backend {
+            #[cfg(feature = "lsapi")]
+            BackendKind::Lsapi => Ok(Self::Lsapi(LsapiBackend::start(config).await?)),
+            #[cfg(feature = "fastcgi")]
+            BackendKind::FastCgi => Ok(Self::FastCgi(FastCgiBackend::start(config).await?)),
+            #[cfg(feature = "embed")]
+            BackendKind::Embed => Ok(Self::Embed(EmbedBackend::start(config).await?)),
+            #[allow(unreachable_patterns)]
+            kind => Err(BackendError::DisabledBackend(kind)),
+        }
+    }
+
+    pub fn kind(&self) -> BackendKind {
+        match self {
+            #[cfg(feature = "lsapi")]
+            Self::Lsapi(_) => BackendKind::Lsapi,
+            #[cfg(feature = "fastcgi")]
+            Self::FastCgi(_) => BackendKind::FastCgi,
+            #[cfg(feature = "embed")]
+            Self::Embed(_) => BackendKind::Embed,
+        }
+    }
+
+    pub async fn execute(&self, request: BackendRequest) -> Result<BackendResponse, BackendError> {
+        match self {
+            #[cfg(feature = "lsapi")]
+            Self::Lsapi(backend) => backend.execute(request).await,
+            #[cfg(feature = "fastcgi")]
+            Self::FastCgi(backend) => backend.execute(request).await,
+            #[cfg(feature = "embed")]
+            Self::Embed(backend) => backend.execute(request).await,
+        }
+    }
+
+    pub async fn execute_stream(
+        &self,
+        request: BackendRequest,
+        output: &OutputSender,
+    ) -> Result<Vec<u8>, BackendError> {
+        match self {
+            #[cfg(feature = "lsapi")]
+            Self::Lsapi(backend) => backend.execute_stream(request, output).await,
+            #[cfg(feature = "fastcgi")]
+            Self::FastCgi(backend) => backend.execute_stream(request, output).await,
+            #[cfg(feature = "embed")]
+            Self::Embed(backend) => backend.execute_stream(request, output).await,
+        }
+    }
+
+    pub async fn health(&self) -> Result<(), BackendError> {
+        match self {
+            #[cfg(feature = "lsapi")]
+            Self::Lsapi(backend) => backend.health().await,
+            #[cfg(feature = "fastcgi")]
+            Self::FastCgi(backend) => backend.health().await,
+            #[cfg(feature = "embed")]
+            Self::Embed(backend) => backend.health().await,
+        }
+    }
+
+    pub async fn abort(&self) -> Result<(), BackendError> {
+        match self {
+            #[cfg(feature = "lsapi")]
+            Self::Lsapi(backend) => backend.abort().await,
+            #[cfg(feature = "fastcgi")]
+            Self::FastCgi(backend) => backend.abort().await,
+            #[cfg(feature = "embed")]
+            Self::Embed(backend) => backend.abort().await,
+        }
+    }
+
+    pub async fn shutdown(&self) -> Result<(), BackendError> {
+        match self {
+            #[cfg(feature = "lsapi")]
+            Self::Lsapi(backend) => backend.shutdown().await,
+            #[cfg(feature = "fastcgi")]
+            Self::FastCgi(backend) => backend.shutdown().await,
+            #[cfg(feature = "embed")]
+            Self::Embed(backend) => backend.shutdown().await,
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use lambda_http::Request;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BodyError {
+    #[error("Invalid size `{0}`, expected a number of bytes with an optional K, M or G")]
+    InvalidSize(String),
+}
+
+/// A size as `php.ini` writes it, like `6M`. Units are powers of 1024.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl ByteSize {
+    const UNITS: [(char, u64); 3] = [('G', 1 << 30), ('M', 1 << 20), ('K', 1 << 10)];
+
+    pub fn bytes(&self) -> u64 {
+        self.0
+    }
+
+    /// `0` disables the limit, like PHP does for `post_max_size`.
+    pub fn is_unlimited(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = BodyError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || BodyError::InvalidSize(value.into());
+        let trimmed = value.trim();
+
+        let (number, multiplier) = match trimmed.chars().last().map(|c| c.to_ascii_uppercase()) {
+            Some(suffix) if suffix.is_ascii_alphabetic() => {
+                let (_, multiplier) = Self::UNITS
+                    .into_iter()
+                    .find(|(unit, _)| *unit == suffix)
+                    .ok_or_else(invalid)?;
+
+                (&trimmed[..trimmed.len() - 1], multiplier)
+            }
+            _ => (trimmed, 1),
+        };
+
+        number
+            .parse::<u64>()
+            .ok()
+            .and_then(|number| number.checked_mul(multiplier))
+            .map(Self)
+            .ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = Self::UNITS
+            .into_iter()
+            .find(|(_, multiplier)| self.0 != 0 && self.0.is_multiple_of(*multiplier));
+
+        match unit {
+            Some((unit, multiplier)) => write!(f, "{}{unit}", self.0 / multiplier),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Whether the body of `req` is over `post_max_size`. PHP would run the
+/// script with empty `$_POST` and `$_FILES` instead of failing, so these are
+/// answered with a 413 before.
+///
+/// Lambda already decoded the body when API Gateway sent it in base64, so
+/// its length is the one PHP reads, multipart bodies included. A
+/// `post_max_size` of `0` lets any body through.
+pub fn is_too_large(req: &Request, post_max_size: ByteSize) -> bool {
+    !post_max_size.is_unlimited() && req.body().len() as u64 > post_max_size.bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lambda_http::request::from_str;
+    use lambda_http::Body;
+
+    #[test]
+    fn parses_sizes_like_php() {
+        let size = |value: &str| value.parse::<ByteSize>().unwrap().bytes();
+
+        assert_eq!(size("6M"), 6 * 1024 * 1024);
+        assert_eq!(size("512k"), 512 * 1024);
+        assert_eq!(size("1G"), 1024 * 1024 * 1024);
+        assert_eq!(size("1000"), 1000);
+        assert!("6MB".parse::<ByteSize>().is_err());
+        assert!("M".parse::<ByteSize>().is_err());
+        assert!("-1".parse::<ByteSize>().is_err());
+
+        assert_eq!(ByteSize(6 * 1024 * 1024).to_string(), "6M");
+        assert_eq!(ByteSize(1536).to_string(), "1536");
+        assert_eq!(ByteSize(0).to_string(), "0");
+    }
+
+    #[test]
+    fn decodes_base64_multipart_bodies() {
+        let req = from_str(include_str!("../tests/fixtures/apigw-v2-multipart.json")).unwrap();
+
+        let body = req.body().to_vec();
+
+        assert!(matches!(req.body(), Body::Binary(_)));
+        assert!(body.starts_with(b"--sigan\r\nContent-Disposition: form-data"));
+        // The PNG signature survives, non UTF-8 bytes included.
+        assert!(body
+            .windows(4)
+            .any(|window| window == [0x89, b'P', b'N', b'G']));
+
+        assert!(!is_too_large(&req, ByteSize(body.len() as u64)));
+        assert!(is_too_large(&req, ByteSize(body.len() as u64 - 1)));
+    }
+
+    #[test]
+    fn treats_zero_as_unlimited() {
+        let req = from_str(include_str!("../tests/fixtures/apigw-v2-multipart.json")).unwrap();
+
+        assert!("0".parse::<ByteSize>().unwrap().is_unlimited());
+        assert!(!is_too_large(&req, ByteSize(0)));
+        assert!(is_too_large(&req, ByteSize(1)));
+    }
+}
@@ -0,0 +1,520 @@
+use crate::context::RuntimeContext;
+use crate::proxy::ClientAddr;
+use lambda_http::request::RequestContext;
+use lambda_http::{Request, RequestExt};
+use std::net::IpAddr;
+use std::path::Path;
+
+/// CGI variables for a PHP request, in insertion order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CgiParams(Vec<(String, String)>);
+
+impl CgiParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a variable, replacing any previous value.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        let value = value.into();
+
+        match self.0.iter_mut().find(|(key, _)| *key == name) {
+            Some((_, current)) => *current = value,
+            None => self.0.push((name, value)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[cfg(any(test, feature = "lsapi", feature = "fastcgi"))]
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Translates a Lambda HTTP request into the variables PHP expects for
+    /// `script_name`, a path relative to `document_root`.
+    pub fn from_request(req: &Request, document_root: &str, script_name: &str) -> Self {
+        let context = RuntimeContext::new(req, Path::new(document_root), script_name);
+
+        Self::from_context(req, &context)
+    }
+
+    /// Translates a Lambda HTTP request into the variables PHP expects for
+    /// the script of its context.
+    pub fn from_context(req: &Request, context: &RuntimeContext) -> Self {
+        let mut params = Self::new();
+
+        let document_root = context.document_root().to_string_lossy();
+        let document_root = document_root.trim_end_matches('/');
+        let https = context.is_https();
+
+        // Clients see scripts under the stage or base path of API Gateway,
+        // like `/prod/index.php`.
+        let public_script_name = format!("{}{}", path_prefix(req), context.script_name());
+
+        params.insert("GATEWAY_INTERFACE", "CGI/1.1");
+        params.insert("SERVER_SOFTWARE", "sigan-runtime");
+        params.insert("SERVER_PROTOCOL", server_protocol(req));
+        params.insert("SERVER_NAME", server_name(req));
+        params.insert("SERVER_PORT", server_port(req, https));
+        params.insert("REQUEST_METHOD", req.method().as_str());
+        params.insert("REQUEST_URI", request_uri(req));
+        params.insert("QUERY_STRING", req.uri().query().unwrap_or_default());
+        params.insert("DOCUMENT_ROOT", document_root);
+        params.insert(
+            "SCRIPT_FILENAME",
+            context.script_filename().to_string_lossy(),
+        );
+        params.insert("SCRIPT_NAME", &public_script_name);
+        params.insert("PHP_SELF", public_script_name);
+        params.insert("REDIRECT_STATUS", "200");
+
+        if https {
+            params.insert("HTTPS", "on");
+        }
+
+        if let Some(path_info) = context.path_info() {
+            params.insert("PATH_INFO", path_info);
+        }
+
+        if let Some(path_translated) = context.path_translated() {
+            params.insert("PATH_TRANSLATED", path_translated.to_string_lossy());
+        }
+
+        if let Some(client_ip) = context.client_ip() {
+            params.insert("REMOTE_ADDR", client_ip.to_string());
+        }
+
+        if let Some(content_type) = header(req, "content-type") {
+            params.insert("CONTENT_TYPE", content_type);
+        }
+
+        // The length of the body PHP gets, whatever the client announced, so
+        // PHP never waits for bytes that are not coming.
+        if !req.body().is_empty() {
+            params.insert("CONTENT_LENGTH", req.body().len().to_string());
+        }
+
+        for name in req.headers().keys() {
+            let name = name.as_str();
+
+            // Content headers have their own variables, and `Proxy` must never
+            // reach PHP as `HTTP_PROXY` (httpoxy).
+            if matches!(name, "content-type" | "content-length" | "proxy") {
+                continue;
+            }
+
+            // `x_forwarded_for` would pass for `x-forwarded-for` once
+            // translated, so names with `_` are dropped like nginx and
+            // Apache do.
+            if name.contains('_') {
+                continue;
+            }
+
+            let separator = if name == "cookie" { "; " } else { ", " };
+
+            let value = req
+                .headers()
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect::<Vec<&str>>()
+                .join(separator);
+
+            params.insert(http_variable(name), value);
+        }
+
+        // Behind CloudFront, the host of the site is only in
+        // `X-Forwarded-Host`, and subdomain networks tell sites apart by it.
+        // The proxy policy removed it unless CloudFront sent it.
+        if let Some(host) = forwarded_host(req) {
+            params.insert("HTTP_HOST", host);
+        }
+
+        params
+    }
+}
+
+impl IntoIterator for CgiParams {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<N: Into<String>, V: Into<String>> FromIterator<(N, V)> for CgiParams {
+    fn from_iter<T: IntoIterator<Item = (N, V)>>(iter: T) -> Self {
+        let mut params = Self::new();
+
+        for (name, value) in iter {
+            params.insert(name, value);
+        }
+
+        params
+    }
+}
+
+/// Maps a header name like `accept-language` to `HTTP_ACCEPT_LANGUAGE`.
+pub fn http_variable(header: &str) -> String {
+    format!("HTTP_{}", header.to_ascii_uppercase().replace('-', "_"))
+}
+
+fn header<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Scheme the client used, as told by the proxy in front.
+pub fn scheme(req: &Request) -> String {
+    header(req, "x-forwarded-proto")
+        .or(req.uri().scheme_str())
+        .unwrap_or("https")
+        .to_ascii_lowercase()
+}
+
+fn server_protocol(req: &Request) -> String {
+    match req.request_context_ref() {
+        Some(RequestContext::ApiGatewayV1(context)) => context.protocol.clone(),
+        Some(RequestContext::ApiGatewayV2(context)) => context.http.protocol.clone(),
+        _ => None,
+    }
+    .unwrap_or_else(|| format!("{:?}", req.version()))
+}
+
+fn server_name(req: &Request) -> String {
+    let host = forwarded_host(req)
+        .or(header(req, "host"))
+        .or(req.uri().host())
+        .or(match req.request_context_ref() {
+            Some(RequestContext::ApiGatewayV1(context)) => context.domain_name.as_deref(),
+            Some(RequestContext::ApiGatewayV2(context)) => context.domain_name.as_deref(),
+            _ => None,
+        })
+        .unwrap_or("localhost");
+
+    strip_port(host).to_string()
+}
+
+fn forwarded_host(req: &Request) -> Option<&str> {
+    header(req, "x-forwarded-host")
+        .and_then(|hosts| hosts.split(',').next())
+        .map(str::trim)
+        .filter(|host| !host.is_empty())
+}
+
+fn server_port(req: &Request, https: bool) -> String {
+    header(req, "x-forwarded-port")
+        .map(str::to_string)
+        .or(req.uri().port_u16().map(|port| port.to_string()))
+        .unwrap_or(if https { "443" } else { "80" }.into())
+}
+
+fn request_uri(req: &Request) -> String {
+    let path = format!("{}{}", path_prefix(req), request_path(req));
+
+    match req.uri().query() {
+        Some(query) if !query.is_empty() => format!("{path}?{query}"),
+        _ => path,
+    }
+}
+
+/// URL path of a request in the site, without the stage or base path of API
+/// Gateway.
+pub fn request_path(req: &Request) -> &str {
+    let path = match req.raw_http_path() {
+        "" => req.uri().path(),
+        path => path,
+    };
+
+    match path.strip_prefix(path_prefix(req).as_str()) {
+        Some("") => "/",
+        Some(path) => path,
+        None => path,
+    }
+}
+
+/// Path API Gateway serves the site under, like `/prod` for the stage in
+/// `https://{api}.execute-api.{region}.amazonaws.com/prod/`, or empty.
+pub fn path_prefix(req: &Request) -> String {
+    match req.request_context_ref() {
+        // The event path is relative to the stage or base path mapping, the
+        // context path is the full one.
+        Some(RequestContext::ApiGatewayV1(context)) => context
+            .path
+            .as_deref()
+            .and_then(|path| path.strip_suffix(req.raw_http_path()))
+            .unwrap_or_default()
+            .trim_end_matches('/')
+            .to_string(),
+        // The raw path includes the stage, unless it is `$default`.
+        Some(RequestContext::ApiGatewayV2(context)) => match context.stage.as_deref() {
+            Some(stage) if stage != "$default" => {
+                let prefix = format!("/{stage}");
+                let path = req.raw_http_path();
+
+                match path == prefix || path.starts_with(&format!("{prefix}/")) {
+                    true => prefix,
+                    false => String::new(),
+                }
+            }
+            _ => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+/// Address of the client, as worked out by the proxy policy, or as seen by
+/// the Lambda event source.
+pub fn remote_addr(req: &Request) -> Option<IpAddr> {
+    if let Some(ClientAddr(ip)) = req.extensions().get::<ClientAddr>() {
+        return Some(*ip);
+    }
+
+    let address = match req.request_context_ref()? {
+        RequestContext::ApiGatewayV1(context) => context.identity.source_ip.as_deref(),
+        RequestContext::ApiGatewayV2(context) => context.http.source_ip.as_deref(),
+        // ALB appends the address of the client to `X-Forwarded-For`.
+        RequestContext::Alb(_) => header(req, "x-forwarded-for")?.rsplit(',').next(),
+    };
+
+    address?.trim().parse().ok()
+}
+
+// Removes the port from `host:port` and `[ipv6]:port`.
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host
+            .split_once(']')
+            .map_or(host, |(address, _)| &host[..address.len() + 1]);
+    }
+
+    host.split_once(':').map_or(host, |(host, _)| host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lambda_http::request::from_str;
+
+    fn params(fixture: &str) -> CgiParams {
+        let req = from_str(fixture).expect("Invalid fixture");
+        CgiParams::from_request(&req, "/mnt/wordpress/", "/index.php")
+    }
+
+    #[test]
+    fn translates_get_request() {
+        let params = params(include_str!("../tests/fixtures/apigw-v2-get.json"));
+
+        assert_eq!(params.get("REQUEST_METHOD"), Some("GET"));
+        assert_eq!(
+            params.get("REQUEST_URI"),
+            Some("/blog/hello-world/?page=2&lang=en")
+        );
+        assert_eq!(params.get("QUERY_STRING"), Some("page=2&lang=en"));
+        assert_eq!(params.get("SERVER_PROTOCOL"), Some("HTTP/1.1"));
+        assert_eq!(params.get("SERVER_NAME"), Some("example.com"));
+        assert_eq!(params.get("SERVER_PORT"), Some("443"));
+        assert_eq!(params.get("HTTPS"), Some("on"));
+        assert_eq!(params.get("REMOTE_ADDR"), Some("203.0.113.10"));
+        assert_eq!(params.get("DOCUMENT_ROOT"), Some("/mnt/wordpress"));
+        assert_eq!(
+            params.get("SCRIPT_FILENAME"),
+            Some("/mnt/wordpress/index.php")
+        );
+        assert_eq!(params.get("SCRIPT_NAME"), Some("/index.php"));
+        assert_eq!(params.get("CONTENT_TYPE"), None);
+        assert_eq!(params.get("CONTENT_LENGTH"), None);
+    }
+
+    #[test]
+    fn translates_headers() {
+        let params = params(include_str!("../tests/fixtures/apigw-v2-get.json"));
+
+        assert_eq!(params.get("HTTP_HOST"), Some("example.com"));
+        assert_eq!(params.get("HTTP_ACCEPT_LANGUAGE"), Some("en-US,en;q=0.9"));
+        assert_eq!(params.get("HTTP_X_FORWARDED_PROTO"), Some("https"));
+        assert_eq!(params.get("HTTP_COOKIE"), Some("wp_lang=en_US;theme=dark"));
+        assert_eq!(params.get("HTTP_PROXY"), None);
+    }
+
+    #[test]
+    fn translates_post_request() {
+        let params = params(include_str!("../tests/fixtures/apigw-v2-post.json"));
+
+        assert_eq!(params.get("REQUEST_METHOD"), Some("POST"));
+        assert_eq!(params.get("REQUEST_URI"), Some("/wp-login.php"));
+        assert_eq!(params.get("QUERY_STRING"), Some(""));
+        assert_eq!(
+            params.get("CONTENT_TYPE"),
+            Some("application/x-www-form-urlencoded")
+        );
+        assert_eq!(params.get("CONTENT_LENGTH"), Some("29"));
+        assert_eq!(params.get("HTTP_CONTENT_TYPE"), None);
+        assert_eq!(params.get("SERVER_PORT"), Some("8443"));
+    }
+
+    #[test]
+    fn sets_the_length_of_the_body_sent() {
+        let mut req = from_str(include_str!("../tests/fixtures/apigw-v2-get.json")).unwrap();
+        req.headers_mut()
+            .insert("content-length", "42".parse().unwrap());
+
+        let params = CgiParams::from_request(&req, "/mnt/wordpress", "/index.php");
+
+        assert_eq!(params.get("CONTENT_LENGTH"), None);
+        assert_eq!(params.get("HTTP_CONTENT_LENGTH"), None);
+    }
+
+    #[test]
+    fn translates_multipart_request() {
+        let params = params(include_str!("../tests/fixtures/apigw-v2-multipart.json"));
+
+        assert_eq!(
+            params.get("CONTENT_TYPE"),
+            Some("multipart/form-data; boundary=sigan")
+        );
+        assert_eq!(params.get("CONTENT_LENGTH"), Some("233"));
+    }
+
+    #[test]
+    fn translates_rest_api_request() {
+        let params = params(include_str!("../tests/fixtures/apigw-v1-get.json"));
+
+        assert_eq!(params.get("REQUEST_METHOD"), Some("GET"));
+        assert_eq!(
+            params.get("REQUEST_URI"),
+            Some("/prod/blog/hello-world/?tag=news")
+        );
+        assert_eq!(params.get("QUERY_STRING"), Some("tag=news"));
+        assert_eq!(params.get("SCRIPT_NAME"), Some("/prod/index.php"));
+        assert_eq!(params.get("PHP_SELF"), Some("/prod/index.php"));
+        assert_eq!(
+            params.get("SCRIPT_FILENAME"),
+            Some("/mnt/wordpress/index.php")
+        );
+        assert_eq!(params.get("SERVER_PROTOCOL"), Some("HTTP/1.1"));
+        assert_eq!(params.get("REMOTE_ADDR"), Some("203.0.113.10"));
+        assert_eq!(params.get("HTTP_COOKIE"), Some("wp_lang=en_US; theme=dark"));
+    }
+
+    #[test]
+    fn translates_http_api_request_with_stage() {
+        let req = from_str(include_str!("../tests/fixtures/apigw-v2-stage.json")).unwrap();
+        let params = CgiParams::from_request(&req, "/mnt/wordpress", "/wp-login.php");
+
+        assert_eq!(request_path(&req), "/wp-login.php");
+        assert_eq!(
+            params.get("REQUEST_URI"),
+            Some("/staging/wp-login.php?redirect_to=%2Fwp-admin%2F")
+        );
+        assert_eq!(params.get("SCRIPT_NAME"), Some("/staging/wp-login.php"));
+        assert_eq!(
+            params.get("SCRIPT_FILENAME"),
+            Some("/mnt/wordpress/wp-login.php")
+        );
+    }
+
+    #[test]
+    fn translates_alb_request() {
+        let req = from_str(include_str!("../tests/fixtures/alb-post.json")).unwrap();
+        let params = CgiParams::from_request(&req, "/mnt/wordpress", "/wp-comments-post.php");
+
+        assert_eq!(request_path(&req), "/wp-comments-post.php");
+        assert_eq!(params.get("REQUEST_METHOD"), Some("POST"));
+        assert_eq!(params.get("REQUEST_URI"), Some("/wp-comments-post.php"));
+        assert_eq!(params.get("SCRIPT_NAME"), Some("/wp-comments-post.php"));
+        assert_eq!(params.get("CONTENT_LENGTH"), Some("31"));
+        assert_eq!(params.get("HTTPS"), None);
+        assert_eq!(params.get("SERVER_PORT"), Some("80"));
+        assert_eq!(params.get("REMOTE_ADDR"), Some("203.0.113.20"));
+        assert_eq!(
+            params.get("HTTP_COOKIE"),
+            Some("wp_lang=en_US; comment_author=Ada")
+        );
+    }
+
+    #[test]
+    fn translates_function_url_request() {
+        let req = from_str(include_str!("../tests/fixtures/function-url-get.json")).unwrap();
+        let params = CgiParams::from_request(&req, "/mnt/wordpress", "/wp-admin/index.php");
+
+        assert_eq!(request_path(&req), "/wp-admin/");
+        assert_eq!(params.get("REQUEST_URI"), Some("/wp-admin/"));
+        assert_eq!(params.get("SCRIPT_NAME"), Some("/wp-admin/index.php"));
+        assert_eq!(
+            params.get("SERVER_NAME"),
+            Some("abcdefghij.lambda-url.us-east-1.on.aws")
+        );
+        assert_eq!(params.get("REMOTE_ADDR"), Some("65.78.31.245"));
+        assert_eq!(params.get("HTTP_COOKIE"), Some("wordpress_logged_in=admin"));
+    }
+
+    #[test]
+    fn prefers_forwarded_hosts() {
+        let mut req = from_str(include_str!("../tests/fixtures/apigw-v2-get.json")).unwrap();
+
+        req.headers_mut().insert(
+            "x-forwarded-host",
+            "site.example.com, proxy.example.com".parse().unwrap(),
+        );
+
+        let params = CgiParams::from_request(&req, "/mnt/wordpress", "/index.php");
+
+        assert_eq!(params.get("SERVER_NAME"), Some("site.example.com"));
+        assert_eq!(params.get("HTTP_HOST"), Some("site.example.com"));
+    }
+
+    #[test]
+    fn passes_path_info() {
+        let req = from_str(include_str!("../tests/fixtures/apigw-v2-get.json")).unwrap();
+
+        let context = RuntimeContext::new(&req, Path::new("/mnt/wordpress"), "/index.php")
+            .with_path_info("/wp-json/wp/v2/posts");
+        let params = CgiParams::from_context(&req, &context);
+
+        assert_eq!(params.get("SCRIPT_NAME"), Some("/index.php"));
+        assert_eq!(
+            params.get("SCRIPT_FILENAME"),
+            Some("/mnt/wordpress/index.php")
+        );
+        assert_eq!(params.get("PATH_INFO"), Some("/wp-json/wp/v2/posts"));
+        assert_eq!(
+            params.get("PATH_TRANSLATED"),
+            Some("/mnt/wordpress/wp-json/wp/v2/posts")
+        );
+
+        let params = CgiParams::from_request(&req, "/mnt/wordpress", "/index.php");
+
+        assert_eq!(params.get("PATH_INFO"), None);
+        assert_eq!(params.get("PATH_TRANSLATED"), None);
+    }
+
+    #[test]
+    fn strips_ports_from_hosts() {
+        assert_eq!(strip_port("example.com:8080"), "example.com");
+        assert_eq!(strip_port("[::1]:8080"), "[::1]");
+        assert_eq!(strip_port("example.com"), "example.com");
+    }
+
+    #[test]
+    fn inserting_replaces_values() {
+        let mut params = CgiParams::new();
+
+        params.insert("SCRIPT_NAME", "/index.php");
+        params.insert("SCRIPT_NAME", "/wp-login.php");
+
+        assert_eq!(params.iter().count(), 1);
+        assert_eq!(params.get("SCRIPT_NAME"), Some("/wp-login.php"));
+    }
+}
@@ -0,0 +1,564 @@
+use crate::backend::BackendKind;
+use crate::body::ByteSize;
+use crate::health::HealthCheck;
+use crate::logging::LogFormat;
+use crate::multisite::Multisite;
+use crate::proxy::TrustedProxy;
+use crate::s3::Bucket;
+use crate::streaming::ResponseMode;
+use crate::uploads::{Uploads, UploadsMode};
+use crate::warmup::Warmup;
+use serde::Deserialize;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read the config file {0}: {1}")]
+    Read(String, #[source] io::Error),
+    #[error("Failed to parse the config file {0}: {1}")]
+    Parse(String, #[source] toml::de::Error),
+    #[error("Invalid `{0}` setting: {1}")]
+    Invalid(&'static str, String),
+}
+
+/// Longest path of a Unix socket, `sun_path` ends with a NUL byte.
+const MAX_SOCKET_PATH: usize = 107;
+
+/// Settings as written in the config file, all optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    document_root: Option<PathBuf>,
+    port: Option<u16>,
+    php_backend: Option<String>,
+    php_ini: Option<PathBuf>,
+    lsapi_socket: Option<String>,
+    fastcgi_socket: Option<String>,
+    rules_file: Option<PathBuf>,
+    error_pages_dir: Option<PathBuf>,
+    log_format: Option<String>,
+    response_mode: Option<String>,
+    post_max_size: Option<String>,
+    upload_max_filesize: Option<String>,
+    trusted_proxy: Option<String>,
+    proxy_secret: Option<String>,
+    multisite: Option<String>,
+    bucket: Option<String>,
+    s3_endpoint: Option<String>,
+    uploads_mode: Option<String>,
+    uploads_path: Option<String>,
+    uploads_url_expires: Option<u64>,
+    uploads_sync_dir: Option<PathBuf>,
+    health_token: Option<String>,
+    health_outbound_url: Option<String>,
+    db_host: Option<String>,
+    warmup_paths: Option<Vec<String>>,
+    warmup_preload: Option<PathBuf>,
+    warmup_timeout: Option<u64>,
+}
+
+/// Where the runtime finds WordPress, PHP and its own configuration, how it
+/// serves requests, and the settings of the features built on top.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    /// `WORDPRESS_ROOT`, `/mnt/wordpress` by default.
+    pub document_root: PathBuf,
+    /// Port of the local server in `serve` mode, `HOST_PORT`.
+    pub port: u16,
+    /// `PHP_BACKEND`, the first one enabled in this build by default.
+    pub php_backend: BackendKind,
+    /// `PHP_INI`, for the backends running a PHP process.
+    pub php_ini: PathBuf,
+    /// `LSAPI_SOCKET`.
+    pub lsapi_socket: String,
+    /// `FASTCGI_SOCKET`.
+    pub fastcgi_socket: String,
+    /// `RULES_FILE`, the default rules apply when it doesn't exist.
+    pub rules_file: PathBuf,
+    /// `ERROR_PAGES_DIR`, the built-in pages apply when it doesn't exist.
+    pub error_pages_dir: PathBuf,
+    /// `LOG_FORMAT`, or the one set for the function in
+    /// `AWS_LAMBDA_LOG_FORMAT`.
+    pub log_format: LogFormat,
+    /// `RESPONSE_MODE`.
+    pub response_mode: ResponseMode,
+    /// `POST_MAX_SIZE`, larger request bodies get a 413.
+    pub post_max_size: ByteSize,
+    /// `UPLOAD_MAX_FILESIZE`, at most `post_max_size`.
+    pub upload_max_filesize: ByteSize,
+    /// `TRUSTED_PROXY`, whose forwarded headers reach the site.
+    pub trusted_proxy: TrustedProxy,
+    /// `PROXY_SECRET`, the value CloudFront sends to be trusted.
+    pub proxy_secret: Option<String>,
+    /// `MULTISITE`, the kind of network served, `None` for a single site.
+    pub multisite: Option<Multisite>,
+    /// `WP_BUCKET` in `AWS_REGION`, at `S3_ENDPOINT` when set. `IS_LOCAL`
+    /// points it to the S3 stand-in of the local development setup.
+    pub bucket: Option<Bucket>,
+    /// `UPLOADS_MODE`.
+    pub uploads_mode: UploadsMode,
+    /// `UPLOADS_PATH`, the URL path of the uploads directory, like
+    /// `/wp-content/uploads/`.
+    pub uploads_path: String,
+    /// `UPLOADS_URL_EXPIRES`, in seconds, for presigned URLs.
+    pub uploads_url_expires: Duration,
+    /// `UPLOADS_SYNC_DIR`, copied to the bucket after requests when set.
+    pub uploads_sync_dir: Option<PathBuf>,
+    /// `HEALTH_TOKEN`, the health check is disabled without it.
+    pub health_token: Option<String>,
+    /// `HEALTH_OUTBOUND_URL`, requested to check outbound access.
+    pub health_outbound_url: String,
+    /// `DB_HOST`, the database the health check connects to.
+    pub db_host: Option<String>,
+    /// `WARMUP_PATHS`, separated by commas, requested during init.
+    pub warmup_paths: Vec<String>,
+    /// `WARMUP_PRELOAD`, a script run during init to fill opcache.
+    pub warmup_preload: Option<PathBuf>,
+    /// `WARMUP_TIMEOUT`, in milliseconds, after which on-demand inits stop
+    /// warming up.
+    pub warmup_timeout: Duration,
+}
+
+impl RuntimeConfig {
+    const FILE: &'static str = "/mnt/config/runtime.toml";
+    /// The payload limit of Lambda, and what `php.ini` sets.
+    const MAX_SIZE: ByteSize = ByteSize(6 << 20);
+
+    /// Loads the config file at `CONFIG_FILE`, then overrides its settings
+    /// with environment variables. The default file is optional, one set
+    /// explicitly must exist.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let file = match std::env::var("CONFIG_FILE") {
+            Ok(file) => Some(PathBuf::from(file)),
+            Err(_) => Some(PathBuf::from(Self::FILE)).filter(|file| file.exists()),
+        };
+
+        let file = match file {
+            Some(file) => ConfigFile::load(&file)?,
+            None => ConfigFile::default(),
+        };
+
+        let config = Self::resolve(file, |name| std::env::var(name).ok())?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Picks each setting from the environment, the file, then the default.
+    fn resolve(
+        file: ConfigFile,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let env = |name| env(name).filter(|value| !value.is_empty());
+
+        let port = match env("HOST_PORT") {
+            Some(port) => Some(port.parse().map_err(|_| {
+                ConfigError::Invalid("port", format!("`{port}` is not a port number"))
+            })?),
+            None => file.port,
+        };
+
+        let php_backend = match parse("php_backend", env("PHP_BACKEND").or(file.php_backend))? {
+            Some(php_backend) => php_backend,
+            None => BackendKind::first_enabled().ok_or(ConfigError::Invalid(
+                "php_backend",
+                "none is enabled".into(),
+            ))?,
+        };
+
+        let log_format = env("LOG_FORMAT")
+            .or(env("AWS_LAMBDA_LOG_FORMAT"))
+            .or(file.log_format);
+
+        let bucket = env("WP_BUCKET").or(file.bucket).map(|name| {
+            let bucket = Bucket::new(name, env("AWS_REGION").unwrap_or(Bucket::REGION.into()));
+
+            let endpoint = env("S3_ENDPOINT")
+                .or(file.s3_endpoint)
+                .or_else(|| env("IS_LOCAL").map(|_| Bucket::LOCAL_ENDPOINT.into()));
+
+            match endpoint {
+                Some(endpoint) => bucket.with_endpoint(endpoint),
+                None => bucket,
+            }
+        });
+
+        let uploads_path = env("UPLOADS_PATH")
+            .or(file.uploads_path)
+            .unwrap_or(Uploads::PATH.into());
+
+        let uploads_url_expires = number(
+            "uploads_url_expires",
+            env("UPLOADS_URL_EXPIRES"),
+            file.uploads_url_expires,
+            "seconds",
+        )?;
+
+        let warmup_paths = match env("WARMUP_PATHS") {
+            Some(paths) => url_paths(paths.split(',')),
+            None => url_paths(file.warmup_paths.iter().flatten().map(String::as_str)),
+        };
+
+        let warmup_timeout = number(
+            "warmup_timeout",
+            env("WARMUP_TIMEOUT"),
+            file.warmup_timeout,
+            "milliseconds",
+        )?;
+
+        let path = |name, value: Option<PathBuf>, default: &str| {
+            env(name)
+                .map(PathBuf::from)
+                .or(value)
+                .unwrap_or(default.into())
+        };
+
+        Ok(Self {
+            document_root: path("WORDPRESS_ROOT", file.document_root, "/mnt/wordpress"),
+            port: port.unwrap_or(3000),
+            php_backend,
+            php_ini: path("PHP_INI", file.php_ini, "/mnt/config/php.ini"),
+            lsapi_socket: env("LSAPI_SOCKET")
+                .or(file.lsapi_socket)
+                .unwrap_or("/tmp/lsphp.sock".into()),
+            fastcgi_socket: env("FASTCGI_SOCKET")
+                .or(file.fastcgi_socket)
+                .unwrap_or("/tmp/.sigan/php-cgi.sock".into()),
+            rules_file: path("RULES_FILE", file.rules_file, "/mnt/config/rules.toml"),
+            error_pages_dir: path(
+                "ERROR_PAGES_DIR",
+                file.error_pages_dir,
+                "/mnt/config/error-pages",
+            ),
+            log_format: parse("log_format", log_format)?.unwrap_or_default(),
+            response_mode: parse("response_mode", env("RESPONSE_MODE").or(file.response_mode))?
+                .unwrap_or_default(),
+            post_max_size: parse("post_max_size", env("POST_MAX_SIZE").or(file.post_max_size))?
+                .unwrap_or(Self::MAX_SIZE),
+            upload_max_filesize: parse(
+                "upload_max_filesize",
+                env("UPLOAD_MAX_FILESIZE").or(file.upload_max_filesize),
+            )?
+            .unwrap_or(Self::MAX_SIZE),
+            trusted_proxy: parse("trusted_proxy", env("TRUSTED_PROXY").or(file.trusted_proxy))?
+                .unwrap_or_default(),
+            proxy_secret: env("PROXY_SECRET").or(file.proxy_secret),
+            multisite: parse("multisite", env("MULTISITE").or(file.multisite))?,
+            bucket,
+            uploads_mode: parse("uploads_mode", env("UPLOADS_MODE").or(file.uploads_mode))?
+                .unwrap_or_default(),
+            uploads_path: format!("/{}/", uploads_path.trim_matches('/')),
+            uploads_url_expires: uploads_url_expires
+                .map(Duration::from_secs)
+                .unwrap_or(Uploads::EXPIRES_IN),
+            uploads_sync_dir: env("UPLOADS_SYNC_DIR")
+                .map(PathBuf::from)
+                .or(file.uploads_sync_dir),
+            health_token: env("HEALTH_TOKEN").or(file.health_token),
+            health_outbound_url: env("HEALTH_OUTBOUND_URL")
+                .or(file.health_outbound_url)
+                .unwrap_or(HealthCheck::OUTBOUND_URL.into()),
+            db_host: env("DB_HOST").or(file.db_host),
+            warmup_paths,
+            warmup_preload: env("WARMUP_PRELOAD")
+                .map(PathBuf::from)
+                .or(file.warmup_preload),
+            warmup_timeout: warmup_timeout
+                .map(Duration::from_millis)
+                .unwrap_or(Warmup::TIMEOUT),
+        })
+    }
+
+    /// Arguments overriding the body limits of `php.ini`, so PHP processes
+    /// agree with the runtime.
+    #[cfg(any(feature = "lsapi", feature = "fastcgi"))]
+    pub fn php_ini_overrides(&self) -> [String; 4] {
+        [
+            "-d".into(),
+            format!("post_max_size={}", self.post_max_size),
+            "-d".into(),
+            format!("upload_max_filesize={}", self.upload_max_filesize),
+        ]
+    }
+
+    /// Checks the settings make sense before anything starts.
+    fn validate(&self) -> Result<(), ConfigError> {
+        if !self.document_root.is_dir() {
+            return Err(ConfigError::Invalid(
+                "document_root",
+                format!("{} is not a directory", self.document_root.display()),
+            ));
+        }
+
+        if !self.php_backend.is_enabled() {
+            return Err(ConfigError::Invalid(
+                "php_backend",
+                format!("`{}` is not enabled in this build", self.php_backend),
+            ));
+        }
+
+        for (setting, socket) in [
+            ("lsapi_socket", &self.lsapi_socket),
+            ("fastcgi_socket", &self.fastcgi_socket),
+        ] {
+            if socket.len() > MAX_SOCKET_PATH {
+                return Err(ConfigError::Invalid(
+                    setting,
+                    format!("{socket} is longer than {MAX_SOCKET_PATH} bytes"),
+                ));
+            }
+        }
+
+        if !self.post_max_size.is_unlimited() && self.upload_max_filesize > self.post_max_size {
+            return Err(ConfigError::Invalid(
+                "upload_max_filesize",
+                format!(
+                    "{} is larger than post_max_size, {}",
+                    self.upload_max_filesize, self.post_max_size
+                ),
+            ));
+        }
+
+        if self.trusted_proxy == TrustedProxy::CloudFront && self.proxy_secret.is_none() {
+            return Err(ConfigError::Invalid(
+                "trusted_proxy",
+                "cloudfront requires a proxy_secret".into(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn parse<T>(setting: &'static str, value: Option<String>) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .map(|value| value.parse())
+        .transpose()
+        .map_err(|error: T::Err| ConfigError::Invalid(setting, error.to_string()))
+}
+
+fn number(
+    setting: &'static str,
+    env: Option<String>,
+    file: Option<u64>,
+    unit: &str,
+) -> Result<Option<u64>, ConfigError> {
+    match env {
+        Some(number) => number.parse().map(Some).map_err(|_| {
+            ConfigError::Invalid(setting, format!("`{number}` is not a number of {unit}"))
+        }),
+        None => Ok(file),
+    }
+}
+
+// Paths from a list, with a leading `/` and without empty entries.
+fn url_paths<'a>(paths: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    paths
+        .into_iter()
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(|path| match path.starts_with('/') {
+            true => path.to_string(),
+            false => format!("/{path}"),
+        })
+        .collect()
+}
+
+impl ConfigFile {
+    fn load(file: &Path) -> Result<Self, ConfigError> {
+        let name = file.display().to_string();
+
+        let contents = std::fs::read_to_string(file)
+            .map_err(|error| ConfigError::Read(name.clone(), error))?;
+
+        toml::from_str(&contents).map_err(|error| ConfigError::Parse(name, error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn resolve(file: &str, env: &[(&str, &str)]) -> Result<RuntimeConfig, ConfigError> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        RuntimeConfig::resolve(toml::from_str(file).unwrap(), |name| env.get(name).cloned())
+    }
+
+    #[test]
+    fn prefers_the_environment_over_the_file() {
+        let config = resolve(
+            r#"
+            document_root = "/srv/wordpress"
+            port = 8080
+            log_format = "json"
+            "#,
+            &[("HOST_PORT", "9000"), ("RESPONSE_MODE", "streaming")],
+        )
+        .unwrap();
+
+        assert_eq!(config.document_root, PathBuf::from("/srv/wordpress"));
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.response_mode, ResponseMode::Streaming);
+        assert_eq!(config.php_ini, PathBuf::from("/mnt/config/php.ini"));
+        assert_eq!(config.lsapi_socket, "/tmp/lsphp.sock");
+    }
+
+    #[test]
+    fn names_invalid_settings() {
+        let error = |file, env| resolve(file, env).unwrap_err().to_string();
+
+        assert_eq!(
+            error("", &[("HOST_PORT", "http")]),
+            "Invalid `port` setting: `http` is not a port number"
+        );
+        assert_eq!(
+            error(r#"response_mode = "chunked""#, &[]),
+            "Invalid `response_mode` setting: Unknown response mode `chunked`, expected one of: buffered, streaming"
+        );
+        assert!(error(r#"php_backend = "fpm""#, &[]).starts_with("Invalid `php_backend` setting"));
+    }
+
+    #[test]
+    fn reads_the_settings_of_features() {
+        let config = resolve(
+            r#"
+            multisite = "subdomain"
+            bucket = "media"
+            uploads_mode = "presigned"
+            uploads_path = "wp-content/files"
+            warmup_paths = ["/", "wp-login.php"]
+            warmup_timeout = 5000
+            "#,
+            &[("UPLOADS_URL_EXPIRES", "60"), ("IS_LOCAL", "true")],
+        )
+        .unwrap();
+
+        assert_eq!(config.multisite, Some(Multisite::Subdomain));
+        assert_eq!(
+            config.bucket,
+            Some(Bucket::new("media", Bucket::REGION).with_endpoint(Bucket::LOCAL_ENDPOINT))
+        );
+        assert_eq!(config.uploads_mode, UploadsMode::Presigned);
+        assert_eq!(config.uploads_path, "/wp-content/files/");
+        assert_eq!(config.uploads_url_expires, Duration::from_secs(60));
+        assert_eq!(config.warmup_paths, ["/", "/wp-login.php"]);
+        assert_eq!(config.warmup_timeout, Duration::from_secs(5));
+        assert_eq!(config.health_token, None);
+
+        let config = resolve("", &[("WARMUP_PATHS", " /, wp-login.php,,/wp-admin/ ")]).unwrap();
+
+        assert_eq!(config.warmup_paths, ["/", "/wp-login.php", "/wp-admin/"]);
+        assert_eq!(config.uploads_path, "/wp-content/uploads/");
+        assert_eq!(config.warmup_timeout, Warmup::TIMEOUT);
+
+        assert_eq!(
+            resolve("", &[("WARMUP_TIMEOUT", "3s")])
+                .unwrap_err()
+                .to_string(),
+            "Invalid `warmup_timeout` setting: `3s` is not a number of milliseconds"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(toml::from_str::<ConfigFile>("document-root = \"/srv\"").is_err());
+    }
+
+    #[test]
+    fn validates_paths() {
+        let config = resolve("", &[("WORDPRESS_ROOT", "/nonexistent/wordpress")]).unwrap();
+
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "Invalid `document_root` setting: /nonexistent/wordpress is not a directory"
+        );
+
+        let directory = std::env::temp_dir();
+        let socket = format!("/tmp/{}.sock", "a".repeat(MAX_SOCKET_PATH));
+
+        let config = resolve(
+            "",
+            &[
+                ("WORDPRESS_ROOT", &directory.to_string_lossy()),
+                ("FASTCGI_SOCKET", &socket),
+            ],
+        )
+        .unwrap();
+
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .starts_with("Invalid `fastcgi_socket` setting"));
+    }
+
+    #[test]
+    fn keeps_body_limits_consistent() {
+        let directory = std::env::temp_dir();
+        let directory = directory.to_string_lossy();
+
+        let config = resolve(r#"post_max_size = "8M""#, &[("WORDPRESS_ROOT", &directory)]).unwrap();
+
+        assert_eq!(config.post_max_size, ByteSize(8 << 20));
+        assert_eq!(config.upload_max_filesize, ByteSize(6 << 20));
+        #[cfg(any(feature = "lsapi", feature = "fastcgi"))]
+        assert_eq!(
+            config.php_ini_overrides()[1..],
+            ["post_max_size=8M", "-d", "upload_max_filesize=6M"]
+        );
+
+        let config = resolve(
+            "",
+            &[
+                ("WORDPRESS_ROOT", &directory),
+                ("UPLOAD_MAX_FILESIZE", "10M"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "Invalid `upload_max_filesize` setting: 10M is larger than post_max_size, 6M"
+        );
+    }
+
+    #[test]
+    fn requires_a_secret_to_trust_cloudfront() {
+        let directory = std::env::temp_dir();
+        let directory = directory.to_string_lossy();
+
+        let config = resolve(
+            r#"trusted_proxy = "cloudfront""#,
+            &[("WORDPRESS_ROOT", &directory)],
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "Invalid `trusted_proxy` setting: cloudfront requires a proxy_secret"
+        );
+
+        let config = resolve(
+            r#"trusted_proxy = "cloudfront""#,
+            &[("WORDPRESS_ROOT", &directory), ("PROXY_SECRET", "s3cr3t")],
+        )
+        .unwrap();
+
+        assert!(config.validate().is_ok());
+    }
+}
@@ -1,32 +1,95 @@
-use fastcgi_client::Params;
-use lambda_http::{Error, Request};
-use std::borrow::Cow;
+use crate::cgi;
+use crate::deadline;
+use lambda_http::{Request, RequestExt};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
+/// Access to the `RuntimeContext` of a request, once routed.
 pub trait RuntimeExt {
     fn set_runtime_context(&mut self, context: RuntimeContext) -> Option<RuntimeContext>;
     fn runtime_context(&self) -> Option<&RuntimeContext>;
 }
 
-#[derive(Clone)]
-pub struct RuntimeContext<'a> {
-    script_name: &'a str,
-    document_root: &'a str,
+/// What the runtime worked out about a request for a PHP script, so every
+/// later stage reads the same values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuntimeContext {
+    document_root: PathBuf,
+    script_filename: PathBuf,
+    script_name: String,
+    path_info: Option<String>,
+    https: bool,
+    client_ip: Option<IpAddr>,
+    request_id: Option<String>,
+    deadline: SystemTime,
 }
 
-impl<'a> RuntimeContext<'a> {
-    pub fn new<S: AsRef<str>>(script_name: S, document_root: S) -> Self {
+impl RuntimeContext {
+    /// The context of `req` running `script_name`, a path relative to
+    /// `document_root`.
+    pub fn new(req: &Request, document_root: &Path, script_name: &str) -> Self {
         Self {
-            script_name: script_name.as_ref(),
-            document_root: document_root.as_ref(),
+            document_root: document_root.to_path_buf(),
+            script_filename: document_root.join(script_name.trim_start_matches('/')),
+            script_name: script_name.into(),
+            path_info: None,
+            https: cgi::scheme(req) == "https",
+            client_ip: cgi::remote_addr(req),
+            request_id: req
+                .lambda_context_ref()
+                .map(|context| context.request_id.clone()),
+            deadline: deadline::deadline(req),
         }
     }
 
+    pub fn document_root(&self) -> &Path {
+        &self.document_root
+    }
+
+    pub fn script_filename(&self) -> &Path {
+        &self.script_filename
+    }
+
+    /// Path of the script relative to the document root, without the stage
+    /// of API Gateway.
     pub fn script_name(&self) -> &str {
         &self.script_name
     }
 
-    pub fn document_root(&self) -> &str {
-        &self.script_name
+    /// Sets the path following the script in the URL path, like
+    /// `/wp-json/wp/v2/posts` for `/index.php/wp-json/wp/v2/posts`.
+    pub fn with_path_info(mut self, path_info: &str) -> Self {
+        self.path_info = Some(path_info.into());
+        self
+    }
+
+    pub fn path_info(&self) -> Option<&str> {
+        self.path_info.as_deref()
+    }
+
+    /// Where `PATH_INFO` would be in the document root, as CGI expects.
+    pub fn path_translated(&self) -> Option<PathBuf> {
+        self.path_info()
+            .map(|path_info| self.document_root.join(path_info.trim_start_matches('/')))
+    }
+
+    pub fn is_https(&self) -> bool {
+        self.https
+    }
+
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+    }
+
+    /// ID of the Lambda invocation, `None` outside of Lambda.
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    /// Time PHP has left to handle the request, until its deadline.
+    pub fn budget(&self) -> Duration {
+        deadline::budget(self.deadline)
     }
 }
 
@@ -34,37 +97,39 @@ impl RuntimeExt for Request {
     fn set_runtime_context(&mut self, context: RuntimeContext) -> Option<RuntimeContext> {
         self.extensions_mut().insert(context)
     }
+
     fn runtime_context(&self) -> Option<&RuntimeContext> {
         self.extensions().get::<RuntimeContext>()
     }
 }
 
-// impl<'a> From<lambda_http::Request> for Params<'a> {
-//     fn from(mut req: lambda_http::Request) -> Self {
-//         let content_type = req
-//             .headers_mut()
-//             .remove("content-type")
-//             .map(|value| value.to_str().unwrap_or_default().to_owned())
-//             .unwrap_or_default()
-//             .to_string();
-//         let content_length = req
-//             .headers_mut()
-//             .remove("content-length")
-//             .map(|value| value.to_str().unwrap_or_default().to_owned())
-//             .map(|value| usize::from_str(&value).unwrap_or_default())
-//             .unwrap_or_default();
-
-//         let request_method = req.method().as_str().to_string();
-//         let request_uri = req.uri().to_string();
-//         let query_string = req.uri().query().unwrap_or_default().to_string();
-
-//         // let document_root =
-
-//         Self::default()
-//             .request_method(request_method)
-//             .request_uri(request_uri)
-//             .query_string(query_string)
-//             .content_type(content_type)
-//             .content_length(content_length)
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lambda_http::Context;
+
+    #[test]
+    fn is_attached_to_requests() {
+        let mut lambda_context = Context::default();
+        lambda_context.request_id = "8f5f0c33".into();
+
+        let mut req = Request::default().with_lambda_context(lambda_context);
+        req.headers_mut()
+            .insert("x-forwarded-proto", "https".parse().unwrap());
+
+        let context = RuntimeContext::new(&req, Path::new("/mnt/wordpress"), "/wp-login.php");
+        req.set_runtime_context(context);
+
+        let context = req.runtime_context().unwrap();
+
+        assert_eq!(context.document_root(), Path::new("/mnt/wordpress"));
+        assert_eq!(
+            context.script_filename(),
+            Path::new("/mnt/wordpress/wp-login.php")
+        );
+        assert_eq!(context.script_name(), "/wp-login.php");
+        assert!(context.is_https());
+        assert_eq!(context.request_id(), Some("8f5f0c33"));
+        assert_eq!(context.path_info(), None);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::backend::{Backend, BackendError, BackendRequest, BackendResponse, OutputSender};
+use lambda_http::request::RequestContext;
+use lambda_http::{Request, RequestExt};
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+/// Time kept to answer with a 504 and restart PHP once the budget is spent.
+const MARGIN: Duration = Duration::from_secs(1);
+
+/// API Gateway gives up on integrations after 29 seconds, whatever the Lambda
+/// timeout.
+const API_GATEWAY_TIMEOUT: Duration = Duration::from_secs(29);
+
+/// Requests served outside of Lambda, like in serve mode, get as long as
+/// they would behind API Gateway.
+const SERVE_TIMEOUT: Duration = API_GATEWAY_TIMEOUT;
+
+/// When a request must be answered by: the invocation deadline, or when API
+/// Gateway gives up if sooner. Outside of Lambda, `SERVE_TIMEOUT` from now.
+pub fn deadline(req: &Request) -> SystemTime {
+    let Some(context) = req.lambda_context_ref() else {
+        return SystemTime::now() + SERVE_TIMEOUT;
+    };
+
+    let deadline = UNIX_EPOCH + Duration::from_millis(context.deadline);
+
+    match gateway_deadline(req) {
+        Some(gateway_deadline) => deadline.min(gateway_deadline),
+        None => deadline,
+    }
+}
+
+/// Time PHP has left to handle a request answered by `deadline`.
+pub fn budget(deadline: SystemTime) -> Duration {
+    remaining(deadline, SystemTime::now())
+}
+
+fn remaining(deadline: SystemTime, now: SystemTime) -> Duration {
+    deadline
+        .duration_since(now)
+        .unwrap_or_default()
+        .saturating_sub(MARGIN)
+}
+
+// Function URLs send the same events as HTTP APIs, but are only bound by the
+// Lambda timeout.
+fn gateway_deadline(req: &Request) -> Option<SystemTime> {
+    let request_time = match req.request_context_ref()? {
+        RequestContext::ApiGatewayV1(context) => context.request_time_epoch,
+        RequestContext::ApiGatewayV2(context) => {
+            let domain_name = context.domain_name.as_deref().unwrap_or_default();
+
+            if domain_name.contains(".lambda-url.") {
+                return None;
+            }
+
+            context.time_epoch
+        }
+        _ => return None,
+    };
+
+    let request_time = UNIX_EPOCH + Duration::from_millis(request_time.try_into().ok()?);
+
+    Some(request_time + API_GATEWAY_TIMEOUT)
+}
+
+/// Executes a request within the budget of its context. Past it, PHP is
+/// aborted and the request fails with `BackendError::Timeout`.
+pub async fn execute(
+    backend: &Backend,
+    request: BackendRequest,
+) -> Result<BackendResponse, BackendError> {
+    let budget = request_budget(&request);
+
+    execute_for(backend, request, budget).await
+}
+
+/// Like `execute`, within `budget`, for requests the runtime makes itself,
+/// like the health check.
+pub async fn execute_for(
+    backend: &Backend,
+    request: BackendRequest,
+    budget: Duration,
+) -> Result<BackendResponse, BackendError> {
+    let labels = Labels::from(&request);
+
+    within(backend, budget, labels, backend.execute(request)).await
+}
+
+/// Like `execute`, sending the output to `output` as PHP writes it.
+pub async fn execute_stream(
+    backend: &Backend,
+    request: BackendRequest,
+    output: &OutputSender,
+) -> Result<Vec<u8>, BackendError> {
+    let budget = request_budget(&request);
+    let labels = Labels::from(&request);
+
+    within(
+        backend,
+        budget,
+        labels,
+        backend.execute_stream(request, output),
+    )
+    .await
+}
+
+// Requests without a context are bound like requests outside of Lambda.
+fn request_budget(request: &BackendRequest) -> Duration {
+    match &request.context {
+        Some(context) => context.budget(),
+        None => SERVE_TIMEOUT - MARGIN,
+    }
+}
+
+// What timeouts are logged with.
+struct Labels {
+    script_name: String,
+    request_id: Option<String>,
+}
+
+impl From<&BackendRequest> for Labels {
+    fn from(request: &BackendRequest) -> Self {
+        Self {
+            script_name: request.params.get("SCRIPT_NAME").unwrap_or_default().into(),
+            request_id: request
+                .context
+                .as_ref()
+                .and_then(|context| context.request_id())
+                .map(Into::into),
+        }
+    }
+}
+
+async fn within<T>(
+    backend: &Backend,
+    budget: Duration,
+    labels: Labels,
+    execution: impl Future<Output = Result<T, BackendError>>,
+) -> Result<T, BackendError> {
+    // The execution is dropped when the budget is spent, so backends see the
+    // request was abandoned before being aborted.
+    match tokio::time::timeout(budget, execution).await {
+        Ok(result) => result,
+        Err(_) => {
+            error!(
+                request_id = labels.request_id,
+                "{} timed out after {} ms",
+                labels.script_name,
+                budget.as_millis()
+            );
+
+            if let Err(error) = backend.abort().await {
+                error!("Failed to abort the PHP request: {}", error);
+            }
+
+            Err(BackendError::Timeout)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lambda_http::aws_lambda_events::apigw::{
+        ApiGatewayProxyRequestContext, ApiGatewayV2httpRequestContext,
+    };
+    use lambda_http::Context;
+
+    fn request(context: RequestContext, deadline: SystemTime) -> Request {
+        let mut lambda_context = Context::default();
+        lambda_context.deadline = deadline.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+
+        Request::default()
+            .with_request_context(context)
+            .with_lambda_context(lambda_context)
+    }
+
+    fn epoch_millis(time: SystemTime) -> i64 {
+        time.duration_since(UNIX_EPOCH).unwrap().as_millis() as i64
+    }
+
+    // Budgets are computed a little after the request is built.
+    fn assert_about(budget: Duration, expected: Duration) {
+        assert!(budget <= expected, "{budget:?} > {expected:?}");
+        assert!(budget > expected - Duration::from_millis(500), "{budget:?}");
+    }
+
+    #[test]
+    fn leaves_a_margin_before_the_deadline() {
+        let now = SystemTime::now();
+
+        assert_eq!(
+            remaining(now + Duration::from_secs(10), now),
+            Duration::from_secs(9)
+        );
+        assert_eq!(
+            remaining(now + Duration::from_millis(500), now),
+            Duration::ZERO
+        );
+        assert_eq!(remaining(now, now + Duration::from_secs(1)), Duration::ZERO);
+    }
+
+    #[test]
+    fn is_bound_by_api_gateway() {
+        let now = SystemTime::now();
+
+        let context = ApiGatewayProxyRequestContext {
+            request_time_epoch: epoch_millis(now - Duration::from_secs(4)),
+            ..Default::default()
+        };
+        let req = request(
+            RequestContext::ApiGatewayV1(context),
+            now + Duration::from_secs(60),
+        );
+
+        assert_about(budget(deadline(&req)), Duration::from_secs(24));
+    }
+
+    #[test]
+    fn is_bound_by_the_lambda_deadline_for_function_urls() {
+        let now = SystemTime::now();
+
+        let context = ApiGatewayV2httpRequestContext {
+            domain_name: Some("abcdefghij.lambda-url.us-east-1.on.aws".into()),
+            time_epoch: epoch_millis(now),
+            ..Default::default()
+        };
+        let req = request(
+            RequestContext::ApiGatewayV2(context),
+            now + Duration::from_secs(60),
+        );
+
+        assert_about(budget(deadline(&req)), Duration::from_secs(59));
+    }
+
+    #[test]
+    fn bounds_requests_without_a_context() {
+        assert_eq!(
+            request_budget(&BackendRequest::default()),
+            SERVE_TIMEOUT - MARGIN
+        );
+    }
+
+    #[test]
+    fn is_bound_outside_of_lambda() {
+        assert_about(
+            budget(deadline(&Request::default())),
+            SERVE_TIMEOUT - MARGIN,
+        );
+    }
+}
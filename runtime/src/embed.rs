@@ -0,0 +1,158 @@
+use crate::backend::{BackendError, BackendRequest, BackendResponse, PhpBackend};
+use crate::config::RuntimeConfig;
+use php_embed::{Interrupt, Php, Request, Response};
+use std::sync::{mpsc, Arc, PoisonError};
+use std::thread;
+use tokio::sync::{oneshot, Mutex};
+use tracing::info;
+
+type Job = (Request, oneshot::Sender<Response>);
+
+/// Where the response of the request PHP runs goes to. The receiver is gone
+/// once the request is abandoned, like past its deadline.
+type Running = Arc<std::sync::Mutex<Option<oneshot::Sender<Response>>>>;
+
+/// Runs PHP in process through the embed SAPI.
+///
+/// PHP is not thread safe, so the interpreter lives on a dedicated thread and
+/// requests are sent to it one at a time.
+pub struct EmbedBackend {
+    sender: Mutex<Option<mpsc::Sender<Job>>>,
+    running: Running,
+    interrupt: Interrupt,
+}
+
+impl PhpBackend for EmbedBackend {
+    // The interpreter is built with its settings, `php_ini` and the body
+    // limits don't apply. The runtime still rejects larger bodies.
+    async fn start(_: &RuntimeConfig) -> Result<Self, BackendError> {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let (ready_sender, ready_receiver) = oneshot::channel();
+        let running = Running::default();
+
+        thread::Builder::new()
+            .name("php".into())
+            .spawn({
+                let running = running.clone();
+
+                move || {
+                    let mut php = Php::init();
+                    let _ = ready_sender.send(php.interrupt_handle());
+
+                    info!("Started embedded PHP interpreter");
+
+                    // Runs until the sender is dropped on shutdown. Requests
+                    // abandoned while queued are skipped.
+                    for (request, response_sender) in receiver {
+                        if response_sender.is_closed() {
+                            continue;
+                        }
+
+                        set_running(&running, Some(response_sender));
+                        let response = php.execute(request);
+
+                        if let Some(response_sender) = set_running(&running, None) {
+                            let _ = response_sender.send(response);
+                        }
+                    }
+
+                    info!("Shutting down embedded PHP interpreter");
+                }
+            })
+            .map_err(BackendError::Process)?;
+
+        let interrupt = ready_receiver
+            .await
+            .map_err(|_| BackendError::Unavailable)?;
+
+        Ok(Self {
+            sender: Mutex::new(Some(sender)),
+            running,
+            interrupt,
+        })
+    }
+
+    async fn execute(&self, request: BackendRequest) -> Result<BackendResponse, BackendError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+
+        self.sender
+            .lock()
+            .await
+            .as_ref()
+            .ok_or(BackendError::Unavailable)?
+            .send((to_embed_request(request), response_sender))
+            .map_err(|_| BackendError::Unavailable)?;
+
+        let response = response_receiver
+            .await
+            .map_err(|_| BackendError::Execution("PHP thread stopped".into()))?;
+
+        // Rebuilds the CGI output, so responses are parsed the same for every backend.
+        let mut stdout = format!("Status: {}\r\n", response.status).into_bytes();
+
+        for header in response.headers {
+            stdout.extend_from_slice(header.as_bytes());
+            stdout.extend_from_slice(b"\r\n");
+        }
+
+        stdout.extend_from_slice(b"\r\n");
+        stdout.extend_from_slice(&response.body);
+
+        Ok(BackendResponse {
+            stdout,
+            stderr: response.stderr,
+        })
+    }
+
+    async fn health(&self) -> Result<(), BackendError> {
+        match self.sender.lock().await.is_some() {
+            true => Ok(()),
+            false => Err(BackendError::Unavailable),
+        }
+    }
+
+    // The embed SAPI doesn't read `php.ini` and has no `max_execution_time`,
+    // so abandoned requests are interrupted rather than left to block the
+    // ones queued behind them. The lock keeps the PHP thread from moving on
+    // to the next request meanwhile, and PHP clears the interruption when a
+    // request starts.
+    async fn abort(&self) -> Result<(), BackendError> {
+        let running = self.running.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if running.as_ref().is_some_and(oneshot::Sender::is_closed) {
+            self.interrupt.interrupt();
+        }
+
+        Ok(())
+    }
+
+    async fn shutdown(&self) -> Result<(), BackendError> {
+        self.sender.lock().await.take();
+        Ok(())
+    }
+}
+
+// Replaces the request PHP runs, returning the previous one.
+fn set_running(
+    running: &Running,
+    response_sender: Option<oneshot::Sender<Response>>,
+) -> Option<oneshot::Sender<Response>> {
+    let mut running = running.lock().unwrap_or_else(PoisonError::into_inner);
+
+    std::mem::replace(&mut *running, response_sender)
+}
+
+fn to_embed_request(request: BackendRequest) -> Request {
+    let param = |name: &str| request.params.get(name).map(str::to_string);
+
+    Request {
+        script_filename: param("SCRIPT_FILENAME").unwrap_or_default(),
+        request_method: param("REQUEST_METHOD").unwrap_or_default(),
+        request_uri: param("REQUEST_URI").unwrap_or_default(),
+        query_string: param("QUERY_STRING").unwrap_or_default(),
+        content_type: param("CONTENT_TYPE"),
+        cookie: param("HTTP_COOKIE"),
+        variables: request.params.clone().into_iter().collect(),
+        body: request.body,
+    }
+}
@@ -0,0 +1,386 @@
+use lambda_http::http::header::{ACCEPT, CONTENT_TYPE};
+use lambda_http::http::{HeaderValue, StatusCode};
+use lambda_http::{Body, Error, Request, RequestExt, Response};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+use tracing::error;
+
+#[derive(Debug, Error)]
+pub enum ErrorPageError {
+    #[error("Failed to read the error page `{0}`: {1}")]
+    Read(String, io::Error),
+}
+
+const HTML_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{{status}} {{reason}}</title>
+</head>
+<body>
+<h1>{{status}} {{reason}}</h1>
+<p>Request ID: {{request_id}}</p>
+</body>
+</html>
+"#;
+
+const JSON_TEMPLATE: &str =
+    r#"{"status":{{status}},"error":"{{reason}}","requestId":"{{request_id}}"}"#;
+
+/// Marks responses the runtime answers with itself, which get an error page.
+#[derive(Clone, Copy, Debug)]
+struct RuntimeError;
+
+/// A response for a status the runtime answers with itself. The body is
+/// replaced with an error page once the response leaves the handler.
+pub fn response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::from(format!(
+        "{} {}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    )));
+
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
+    response.extensions_mut().insert(RuntimeError);
+
+    response
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Format {
+    Html,
+    Json,
+}
+
+impl Format {
+    const ALL: [Self; 2] = [Self::Html, Self::Json];
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Html => "html",
+            Self::Json => "json",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Html => "text/html; charset=utf-8",
+            Self::Json => "application/json",
+        }
+    }
+
+    /// Picks JSON when the client ranks it above HTML, like API clients and
+    /// `fetch` calls do.
+    fn from_accept(accept: &str) -> Self {
+        let mut html = 0.0;
+        let mut json = 0.0;
+
+        for media_range in accept.split(',') {
+            let mut parts = media_range.split(';');
+            let media_type = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+
+            let quality: f32 = parts
+                .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+                .find_map(|quality| quality.parse().ok())
+                .unwrap_or(1.0);
+
+            match media_type.as_str() {
+                "text/html" | "application/xhtml+xml" | "text/*" => html = f32::max(html, quality),
+                "application/json" | "application/*" => json = f32::max(json, quality),
+                // Listed types rank above the wildcard, HTML stays the default.
+                "*/*" => {
+                    html = f32::max(html, quality * 0.9);
+                    json = f32::max(json, quality * 0.5);
+                }
+                media_type if media_type.ends_with("+json") => json = f32::max(json, quality),
+                _ => {}
+            }
+        }
+
+        match json > html {
+            true => Self::Json,
+            false => Self::Html,
+        }
+    }
+}
+
+/// What error pages are rendered for, read from the request before the
+/// handler takes it.
+pub struct PageRequest {
+    format: Format,
+    request_id: String,
+}
+
+impl PageRequest {
+    pub fn from_request(req: &Request) -> Self {
+        let accept = req
+            .headers()
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .unwrap_or_default();
+
+        Self {
+            format: Format::from_accept(accept),
+            request_id: req
+                .lambda_context_ref()
+                .map(|context| context.request_id.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Templates of the error pages, in HTML and JSON. `{{status}}`, `{{reason}}`
+/// and `{{request_id}}` are replaced when rendering.
+#[derive(Debug, Default)]
+pub struct ErrorPages {
+    templates: HashMap<(Option<u16>, Format), String>,
+}
+
+impl ErrorPages {
+    /// Loads `{status}.html` and `{status}.json` templates, and
+    /// `default.html` and `default.json` for the other statuses. Pages
+    /// missing there use the built-in templates.
+    pub fn load(directory: &Path) -> Result<Self, ErrorPageError> {
+        let mut templates = HashMap::new();
+
+        let entries = match std::fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => return Err(ErrorPageError::Read(directory.display().to_string(), error)),
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            let (Some(name), Some(extension)) = (path.file_stem(), path.extension()) else {
+                continue;
+            };
+
+            let status = match name.to_string_lossy().as_ref() {
+                "default" => None,
+                name => match name.parse::<StatusCode>() {
+                    Ok(status) => Some(status.as_u16()),
+                    Err(_) => continue,
+                },
+            };
+
+            let Some(format) = Format::ALL
+                .into_iter()
+                .find(|format| extension == format.extension())
+            else {
+                continue;
+            };
+
+            let template = std::fs::read_to_string(&path)
+                .map_err(|error| ErrorPageError::Read(path.display().to_string(), error))?;
+
+            templates.insert((status, format), template);
+        }
+
+        Ok(Self { templates })
+    }
+
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    /// Replaces the body of runtime responses with the error page for their
+    /// status, other responses are left as they are.
+    pub fn render<B: From<Body>>(&self, page: &PageRequest, response: Response<B>) -> Response<B> {
+        if response.extensions().get::<RuntimeError>().is_none() {
+            return response;
+        }
+
+        let (mut parts, _) = response.into_parts();
+
+        let status = parts.status.as_u16();
+        let reason = parts.status.canonical_reason().unwrap_or_default();
+
+        let template = self
+            .templates
+            .get(&(Some(status), page.format))
+            .or(self.templates.get(&(None, page.format)))
+            .map(String::as_str)
+            .unwrap_or(match page.format {
+                Format::Html => HTML_TEMPLATE,
+                Format::Json => JSON_TEMPLATE,
+            });
+
+        let escape = |value: &str| match page.format {
+            Format::Html => escape_html(value),
+            Format::Json => escape_json(value),
+        };
+
+        let body = template
+            .replace("{{status}}", &status.to_string())
+            .replace("{{reason}}", &escape(reason))
+            .replace("{{request_id}}", &escape(&page.request_id));
+
+        parts.headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(page.format.content_type()),
+        );
+
+        Response::from_parts(parts, Body::from(body).into())
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn escape_json(value: &str) -> String {
+    let quoted = serde_json::Value::from(value).to_string();
+
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+/// Runs `handler` as a task of its own, so a panic answers with a 500 like any
+/// failure, then renders the error pages.
+pub async fn respond<B, F>(
+    pages: &ErrorPages,
+    req: Request,
+    handler: impl FnOnce(Request) -> F,
+) -> Response<B>
+where
+    B: From<Body> + Send + 'static,
+    F: Future<Output = Result<Response<B>, Error>> + Send + 'static,
+{
+    let page = PageRequest::from_request(&req);
+
+    let response = match tokio::spawn(handler(req)).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(error)) => {
+            error!("Failed to handle the request: {}", error);
+            response(StatusCode::BAD_GATEWAY).map(B::from)
+        }
+        Err(error) => {
+            error!("The handler panicked: {}", error);
+            response(StatusCode::INTERNAL_SERVER_ERROR).map(B::from)
+        }
+    };
+
+    pages.render(&page, response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::DocumentRoot;
+    use lambda_http::Context;
+
+    fn page_request(accept: &str) -> PageRequest {
+        let mut context = Context::default();
+        context.request_id = "8f5f0c33-<id>".into();
+
+        let mut req = Request::default().with_lambda_context(context);
+        req.headers_mut().insert(ACCEPT, accept.parse().unwrap());
+
+        PageRequest::from_request(&req)
+    }
+
+    fn body(response: Response<Body>) -> String {
+        String::from_utf8(response.body().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn picks_the_format_from_accept() {
+        let format = |accept| Format::from_accept(accept);
+
+        assert_eq!(
+            format("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+            Format::Html
+        );
+        assert_eq!(format("application/json"), Format::Json);
+        assert_eq!(format("application/json, text/plain, */*"), Format::Json);
+        assert_eq!(format("application/problem+json"), Format::Json);
+        assert_eq!(format("text/html;q=0.5, application/json"), Format::Json);
+        assert_eq!(format("*/*"), Format::Html);
+        assert_eq!(format(""), Format::Html);
+    }
+
+    #[test]
+    fn renders_built_in_pages() {
+        let pages = ErrorPages::default();
+
+        let timeout = pages.render(
+            &page_request("application/json"),
+            response(StatusCode::GATEWAY_TIMEOUT),
+        );
+
+        assert_eq!(timeout.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(timeout.headers()[CONTENT_TYPE], "application/json");
+
+        let json: serde_json::Value = serde_json::from_str(&body(timeout)).unwrap();
+        assert_eq!(json["status"], 504);
+        assert_eq!(json["error"], "Gateway Timeout");
+        assert_eq!(json["requestId"], "8f5f0c33-<id>");
+
+        let forbidden = pages.render(&page_request("text/html"), response(StatusCode::FORBIDDEN));
+
+        assert!(body(forbidden).contains("<p>Request ID: 8f5f0c33-&lt;id&gt;</p>"));
+    }
+
+    #[test]
+    fn renders_configured_pages() {
+        let root = DocumentRoot::new("error-pages")
+            .file("404.html", "<p>Lost: {{request_id}}</p>")
+            .file("default.html", "<p>{{status}} {{reason}}</p>")
+            .file("notes.txt", "");
+
+        let pages = ErrorPages::load(root.path()).unwrap();
+
+        assert_eq!(pages.len(), 2);
+
+        let render = |status| body(pages.render(&page_request("text/html"), response(status)));
+
+        assert_eq!(
+            render(StatusCode::NOT_FOUND),
+            "<p>Lost: 8f5f0c33-&lt;id&gt;</p>"
+        );
+        assert_eq!(
+            render(StatusCode::SERVICE_UNAVAILABLE),
+            "<p>503 Service Unavailable</p>"
+        );
+
+        // JSON clients still get the built-in page.
+        let response = pages.render(
+            &page_request("application/json"),
+            response(StatusCode::NOT_FOUND),
+        );
+        assert!(body(response).starts_with(r#"{"status":404"#));
+    }
+
+    #[test]
+    fn leaves_php_responses_alone() {
+        let mut php_response = Response::new(Body::from("Custom 404 from the theme"));
+        *php_response.status_mut() = StatusCode::NOT_FOUND;
+
+        let response = ErrorPages::default().render(&page_request("text/html"), php_response);
+
+        assert_eq!(body(response), "Custom 404 from the theme");
+    }
+
+    #[tokio::test]
+    async fn answers_panics_with_a_500() {
+        let response = respond(&ErrorPages::default(), Request::default(), |_| async {
+            panic!("boom");
+
+            #[allow(unreachable_code)]
+            Ok(Response::new(Body::Empty))
+        })
+        .await;
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,445 @@
+use crate::sync::UploadsSync;
+use lambda_http::http::Method;
+use lambda_http::{Request, RequestExt};
+use serde::Deserialize;
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+#[derive(Debug, Error)]
+pub enum ExtensionError {
+    #[error("Failed to reach the Extensions API: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("The Extensions API answered {0}: {1}")]
+    Status(u16, String),
+    #[error("The Extensions API did not return an extension identifier")]
+    MissingIdentifier,
+    #[error("Failed to read an extension event: {0}")]
+    Event(#[from] serde_json::Error),
+}
+
+/// Events sent by Lambda to extensions.
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(tag = "eventType", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ExtensionEvent {
+    #[serde(rename_all = "camelCase")]
+    Invoke {
+        request_id: String,
+        deadline_ms: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    Shutdown {
+        shutdown_reason: String,
+        deadline_ms: u64,
+    },
+}
+
+/// The runtime registered as an internal extension. Lambda waits for
+/// extensions to ask for the next event before freezing the environment, so
+/// work can run after the response is sent.
+pub struct Extension {
+    endpoint: String,
+    identifier: String,
+    client: reqwest::Client,
+}
+
+impl Extension {
+    const NAME: &'static str = "sigan-runtime";
+    // Internal extensions can't register for `SHUTDOWN`: Lambda sends the
+    // runtime a SIGTERM instead, once an extension is registered.
+    const EVENTS: &'static str = r#"{"events":["INVOKE"]}"#;
+
+    /// Registers with the API in `AWS_LAMBDA_RUNTIME_API`, `None` outside of
+    /// Lambda.
+    pub async fn register_from_env() -> Result<Option<Self>, ExtensionError> {
+        match std::env::var("AWS_LAMBDA_RUNTIME_API") {
+            Ok(api) => Self::register(&api).await.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub async fn register(api: &str) -> Result<Self, ExtensionError> {
+        let endpoint = format!("http://{api}/2020-01-01/extension");
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post(format!("{endpoint}/register"))
+            .header("Lambda-Extension-Name", Self::NAME)
+            .body(Self::EVENTS)
+            .send()
+            .await?;
+
+        let response = check(response).await?;
+
+        let identifier = response
+            .headers()
+            .get("Lambda-Extension-Identifier")
+            .and_then(|identifier| identifier.to_str().ok())
+            .ok_or(ExtensionError::MissingIdentifier)?
+            .to_string();
+
+        Ok(Self {
+            endpoint,
+            identifier,
+            client,
+        })
+    }
+
+    /// Waits for the next event, which also tells Lambda the extension is done
+    /// with the previous one.
+    pub async fn next_event(&self) -> Result<ExtensionEvent, ExtensionError> {
+        let response = self
+            .client
+            .get(format!("{}/event/next", self.endpoint))
+            .header("Lambda-Extension-Identifier", &self.identifier)
+            .send()
+            .await?;
+
+        let body = check(response).await?.bytes().await?;
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Runs the post-invoke work of every invocation, until Lambda shuts down
+    /// or the handler is gone.
+    pub async fn run(
+        self,
+        post_invoke: &PostInvoke,
+        mut invocations: mpsc::UnboundedReceiver<InvocationReport>,
+    ) -> Result<(), ExtensionError> {
+        loop {
+            match self.next_event().await? {
+                ExtensionEvent::Invoke {
+                    request_id,
+                    deadline_ms,
+                } => {
+                    // Invocations Lambda failed to hand to the handler never
+                    // report back, so the wait ends at their deadline.
+                    let deadline = UNIX_EPOCH + Duration::from_millis(deadline_ms);
+                    let timeout = deadline
+                        .duration_since(SystemTime::now())
+                        .unwrap_or_default();
+
+                    let report = report(&mut invocations, &request_id);
+
+                    match tokio::time::timeout(timeout, report).await {
+                        Ok(Some(InvocationReport {
+                            changes_since: Some(since),
+                            ..
+                        })) => post_invoke.run(since).await,
+                        Ok(Some(_)) => {}
+                        Ok(None) => return Ok(()),
+                        Err(_) => warn!("Invocation {} did not reach the handler", request_id),
+                    }
+
+                    flush_logs();
+                }
+                ExtensionEvent::Shutdown {
+                    shutdown_reason, ..
+                } => {
+                    info!("Lambda is shutting down: {}", shutdown_reason);
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Waits for the report of the invocation `request_id`, `None` once the
+/// handler is gone. Reports of invocations whose wait already ended are late,
+/// and dropped so they don't stand for this one.
+async fn report(
+    invocations: &mut mpsc::UnboundedReceiver<InvocationReport>,
+    request_id: &str,
+) -> Option<InvocationReport> {
+    while let Some(report) = invocations.recv().await {
+        match report.request_id == request_id {
+            true => return Some(report),
+            false => warn!("Dropped the late report of {}", report.request_id),
+        }
+    }
+
+    None
+}
+
+async fn check(response: reqwest::Response) -> Result<reqwest::Response, ExtensionError> {
+    let status = response.status();
+
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.text().await.unwrap_or_default();
+
+    Err(ExtensionError::Status(status.as_u16(), body))
+}
+
+/// Writes out what is buffered on stdout, where the logs go, and on stderr,
+/// where embedded PHP writes.
+pub fn flush_logs() {
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+}
+
+/// Work to do once PHP ran, like syncing the uploads. It runs before the
+/// response, unless an extension defers it until after.
+pub struct PostInvoke {
+    uploads_sync: Option<UploadsSync>,
+    deferred: Option<mpsc::UnboundedSender<InvocationReport>>,
+}
+
+impl PostInvoke {
+    pub fn new(uploads_sync: Option<UploadsSync>) -> Self {
+        Self {
+            uploads_sync,
+            deferred: None,
+        }
+    }
+
+    /// Hands the work to an extension, through the returned receiver.
+    pub fn deferred(
+        uploads_sync: Option<UploadsSync>,
+    ) -> (Self, mpsc::UnboundedReceiver<InvocationReport>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let post_invoke = Self {
+            uploads_sync,
+            deferred: Some(sender),
+        };
+
+        (post_invoke, receiver)
+    }
+
+    /// Starts tracking the invocation of `req`, which reports back when
+    /// dropped.
+    pub fn start(&self, req: &Request) -> Invocation {
+        let request_id = req
+            .lambda_context_ref()
+            .map(|context| context.request_id.clone())
+            .unwrap_or_default();
+
+        // `GET` and `HEAD` requests are not expected to change files.
+        let started = match *req.method() {
+            Method::GET | Method::HEAD => None,
+            _ => Some(SystemTime::now()),
+        };
+
+        Invocation {
+            sender: self.deferred.clone(),
+            started,
+            report: InvocationReport {
+                request_id,
+                changes_since: None,
+            },
+        }
+    }
+
+    /// Records that PHP ran for `invocation`, running the work right away
+    /// when it is not deferred.
+    pub async fn executed(&self, invocation: &mut Invocation) {
+        let Some(started) = invocation.started else {
+            return;
+        };
+
+        match self.deferred {
+            Some(_) => invocation.report.changes_since = Some(started),
+            None => self.run(started).await,
+        }
+    }
+
+    /// Runs the work for what changed since `since`.
+    pub async fn run(&self, since: SystemTime) {
+        if let Some(uploads_sync) = &self.uploads_sync {
+            uploads_sync.sync(since).await;
+        }
+    }
+}
+
+/// An invocation in progress. Dropped on every path out of the handler, so
+/// the extension never waits for an invocation that is over.
+pub struct Invocation {
+    sender: Option<mpsc::UnboundedSender<InvocationReport>>,
+    /// When a request that may change files started.
+    started: Option<SystemTime>,
+    report: InvocationReport,
+}
+
+/// What the handler tells the extension about an invocation once it is over.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct InvocationReport {
+    pub request_id: String,
+    /// When the invocation started, if PHP ran and the post-invoke work is
+    /// due for what changed since.
+    pub changes_since: Option<SystemTime>,
+}
+
+impl Drop for Invocation {
+    fn drop(&mut self) {
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.send(std::mem::take(&mut self.report));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// A local Extensions API stand-in, answering each request with the next
+    /// headers and body, and reporting the request heads.
+    async fn stand_in(
+        responses: Vec<(&'static str, String)>,
+    ) -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api = listener.local_addr().unwrap().to_string();
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            for (headers, body) in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+
+                // Requests to the Extensions API have small bodies, read
+                // along the head.
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    let mut buffer = [0; 1024];
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                }
+
+                let _ = sender.send(String::from_utf8_lossy(&request).into_owned());
+
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\n{headers}content-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (api, receiver)
+    }
+
+    fn invoked(request_id: &str, method: Method) -> Request {
+        let mut context = lambda_http::Context::default();
+        context.request_id = request_id.into();
+
+        let mut req = Request::default().with_lambda_context(context);
+        *req.method_mut() = method;
+
+        req
+    }
+
+    fn deadline() -> u64 {
+        let deadline = SystemTime::now() + Duration::from_secs(5);
+
+        deadline.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+    }
+
+    #[tokio::test]
+    async fn registers_and_runs_until_shutdown() {
+        let (api, mut requests) = stand_in(vec![
+            ("lambda-extension-identifier: 8f2e\r\n", "{}".into()),
+            (
+                "",
+                format!(
+                    r#"{{"eventType":"INVOKE","requestId":"a1","deadlineMs":{},"invokedFunctionArn":"arn"}}"#,
+                    deadline()
+                ),
+            ),
+            (
+                "",
+                format!(
+                    r#"{{"eventType":"SHUTDOWN","shutdownReason":"spindown","deadlineMs":{}}}"#,
+                    deadline()
+                ),
+            ),
+        ])
+        .await;
+
+        let extension = Extension::register(&api).await.unwrap();
+
+        let register = requests.recv().await.unwrap();
+        assert!(register.starts_with("POST /2020-01-01/extension/register "));
+        assert!(register.contains("lambda-extension-name: sigan-runtime"));
+
+        let (post_invoke, invocations) = PostInvoke::deferred(None);
+
+        // The handler ran for the invocation, without PHP.
+        drop(post_invoke.start(&invoked("a1", Method::POST)));
+
+        extension.run(&post_invoke, invocations).await.unwrap();
+
+        for _ in 0..2 {
+            let next = requests.recv().await.unwrap();
+            assert!(next.starts_with("GET /2020-01-01/extension/event/next "));
+            assert!(next.contains("lambda-extension-identifier: 8f2e"));
+        }
+    }
+
+    #[tokio::test]
+    async fn invocations_report_when_dropped() {
+        let (post_invoke, mut invocations) = PostInvoke::deferred(None);
+
+        drop(post_invoke.start(&invoked("a1", Method::POST)));
+
+        let mut invocation = post_invoke.start(&invoked("a2", Method::POST));
+        let started = invocation.started;
+        post_invoke.executed(&mut invocation).await;
+        drop(invocation);
+
+        // Nothing is due for requests not expected to change files.
+        let mut invocation = post_invoke.start(&invoked("a3", Method::GET));
+        post_invoke.executed(&mut invocation).await;
+        drop(invocation);
+
+        let report = |request_id: &str, changes_since| InvocationReport {
+            request_id: request_id.into(),
+            changes_since,
+        };
+
+        assert!(started.is_some());
+        assert_eq!(invocations.recv().await, Some(report("a1", None)));
+        assert_eq!(invocations.recv().await, Some(report("a2", started)));
+        assert_eq!(invocations.recv().await, Some(report("a3", None)));
+    }
+
+    #[tokio::test]
+    async fn drops_late_reports() {
+        let (post_invoke, mut invocations) = PostInvoke::deferred(None);
+
+        // `a1` reports after its wait ended, while `a2` is awaited.
+        let mut late = post_invoke.start(&invoked("a1", Method::POST));
+        post_invoke.executed(&mut late).await;
+        drop(late);
+        drop(post_invoke.start(&invoked("a2", Method::POST)));
+
+        let received = report(&mut invocations, "a2").await.unwrap();
+
+        assert_eq!(received.request_id, "a2");
+        assert_eq!(received.changes_since, None);
+
+        drop(post_invoke);
+        assert_eq!(report(&mut invocations, "a3").await, None);
+    }
+
+    #[test]
+    fn parses_events() {
+        let event: ExtensionEvent = serde_json::from_str(
+            r#"{"eventType":"SHUTDOWN","shutdownReason":"timeout","deadlineMs":1700000000000}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            event,
+            ExtensionEvent::Shutdown {
+                shutdown_reason: "timeout".into(),
+                deadline_ms: 1_700_000_000_000,
+            }
+        );
+    }
+}
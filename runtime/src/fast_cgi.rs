@@ -1,59 +1,222 @@
+use crate::backend::{
+    BackendError, BackendRequest, BackendResponse, Output, OutputSender, PhpBackend,
+};
+use crate::config::RuntimeConfig;
+use crate::process::{prepare_socket, PhpProcess};
+use bytes::Bytes;
 use fastcgi_client::conn::KeepAlive;
-use fastcgi_client::Client;
-use lambda_http::{Request, RequestExt};
-use std::sync::{Arc, Mutex};
+use fastcgi_client::response::Content;
+use fastcgi_client::{Client, ClientError, Params, Request};
+use std::io;
 use tokio::net::UnixStream;
+use tokio::sync::Mutex;
 use tokio::time::{interval, Duration, Instant};
-use tracing::info;
+use tracing::{info, warn};
 
-pub struct FastCgiClient {
-    client: Arc<Mutex<Client<UnixStream, KeepAlive>>>,
+/// Runs PHP through a `php-cgi` process speaking FastCGI.
+pub struct FastCgiBackend {
+    socket: String,
+    process: PhpProcess,
+    client: Mutex<Client<UnixStream, KeepAlive>>,
 }
 
-impl FastCgiClient {
-    pub async fn new(socket: &str) -> Self {
-        let stream = Self::connect_to_server(socket).await;
-        let client = Client::new_keep_alive(stream);
+impl FastCgiBackend {
+    const COMMAND: &'static str = "php-cgi";
+}
+
+impl PhpBackend for FastCgiBackend {
+    async fn start(config: &RuntimeConfig) -> Result<Self, BackendError> {
+        let socket = config.fastcgi_socket.clone();
+        let php_ini = config.php_ini.to_string_lossy();
+
+        prepare_socket(&socket).map_err(BackendError::Process)?;
+
+        let overrides = config.php_ini_overrides();
+        let mut args = vec!["-b", &socket, "-c", &php_ini];
+        args.extend(overrides.iter().map(String::as_str));
+
+        let process = PhpProcess::spawn(Self::COMMAND, &args).map_err(BackendError::Process)?;
+
+        let stream = connect_to_server(&socket)
+            .await
+            .map_err(BackendError::Connection)?;
+
+        Ok(Self {
+            socket,
+            process,
+            client: Mutex::new(Client::new_keep_alive(stream)),
+        })
+    }
+
+    async fn execute(&self, request: BackendRequest) -> Result<BackendResponse, BackendError> {
+        let mut client = self.client.lock().await;
+
+        let params = to_params(&request);
+
+        match buffer_request(&mut client, params.clone(), &request.body).await {
+            Ok(result) => result,
+            Err(error) => {
+                // The connection may have been closed by php-cgi, retry once.
+                // Requests that were sent are never retried, the script may
+                // have run.
+                warn!("FastCGI request failed, reconnecting: {}", error);
+
+                let stream = connect_to_server(&self.socket)
+                    .await
+                    .map_err(BackendError::Connection)?;
+
+                *client = Client::new_keep_alive(stream);
 
-        Self {
-            client: Arc::new(Mutex::new(client)),
+                buffer_request(&mut client, params, &request.body)
+                    .await
+                    .map_err(|error| BackendError::Execution(error.to_string()))?
+            }
+        }
+    }
+
+    async fn execute_stream(
+        &self,
+        request: BackendRequest,
+        output: &OutputSender,
+    ) -> Result<Vec<u8>, BackendError> {
+        let mut client = self.client.lock().await;
+
+        let params = to_params(&request);
+
+        match stream_request(&mut client, params.clone(), &request.body, output).await {
+            Ok(result) => result,
+            Err(error) => {
+                // The connection may have been closed by php-cgi, retry once.
+                warn!("FastCGI request failed, reconnecting: {}", error);
+
+                let stream = connect_to_server(&self.socket)
+                    .await
+                    .map_err(BackendError::Connection)?;
+
+                *client = Client::new_keep_alive(stream);
+
+                stream_request(&mut client, params, &request.body, output)
+                    .await
+                    .map_err(|error| BackendError::Execution(error.to_string()))?
+            }
         }
     }
 
-    pub async fn send(&self, script_name: &str, req: Request) {
-        println!("context: {:#?} {:#?}", script_name, req.request_context());
+    async fn health(&self) -> Result<(), BackendError> {
+        match self.process.is_running().await {
+            true => Ok(()),
+            false => Err(BackendError::Unavailable),
+        }
     }
+
+    async fn abort(&self) -> Result<(), BackendError> {
+        let mut client = self.client.lock().await;
+
+        prepare_socket(&self.socket).map_err(BackendError::Process)?;
+
+        self.process
+            .restart()
+            .await
+            .map_err(BackendError::Process)?;
+
+        let stream = connect_to_server(&self.socket)
+            .await
+            .map_err(BackendError::Connection)?;
+
+        *client = Client::new_keep_alive(stream);
+
+        Ok(())
+    }
+
+    async fn shutdown(&self) -> Result<(), BackendError> {
+        self.process.kill().await.map_err(BackendError::Process)
+    }
+}
+
+fn to_params(request: &BackendRequest) -> Params<'_> {
+    request
+        .params
+        .iter()
+        .fold(Params::default(), |mut params, (name, value)| {
+            params.insert(name.into(), value.into());
+            params
+        })
+}
+
+// Sends a request and reads its whole output. The outer error is for
+// requests that could not be sent, which are safe to retry.
+async fn buffer_request(
+    client: &mut Client<UnixStream, KeepAlive>,
+    params: Params<'_>,
+    mut body: &[u8],
+) -> Result<Result<BackendResponse, BackendError>, ClientError> {
+    let mut stream = client
+        .execute_stream(Request::new(params, &mut body))
+        .await?;
+    let mut response = BackendResponse::default();
+
+    while let Some(content) = stream.next().await {
+        match content {
+            Ok(Content::Stdout(chunk)) => response.stdout.extend_from_slice(chunk),
+            Ok(Content::Stderr(chunk)) => response.stderr.extend_from_slice(chunk),
+            Err(error) => return Ok(Err(BackendError::Execution(error.to_string()))),
+        }
+    }
+
+    Ok(Ok(response))
 }
 
-impl Clone for FastCgiClient {
-    fn clone(&self) -> Self {
-        Self {
-            client: Arc::clone(&self.client),
+// Sends a request and forwards its output as it arrives. The outer error is
+// for requests that could not be sent, which are safe to retry.
+async fn stream_request(
+    client: &mut Client<UnixStream, KeepAlive>,
+    params: Params<'_>,
+    mut body: &[u8],
+    output: &OutputSender,
+) -> Result<Result<Vec<u8>, BackendError>, ClientError> {
+    let mut stream = client
+        .execute_stream(Request::new(params, &mut body))
+        .await?;
+    let mut stderr = Vec::new();
+
+    // Reads to the end even when the client is gone, so the connection can
+    // be reused.
+    while let Some(content) = stream.next().await {
+        match content {
+            Ok(Content::Stdout(chunk)) => {
+                let chunk = Bytes::copy_from_slice(chunk);
+                let _ = output.send(Ok(Output::Stdout(chunk))).await;
+            }
+            Ok(Content::Stderr(chunk)) => {
+                stderr.extend_from_slice(chunk);
+
+                let chunk = Bytes::copy_from_slice(chunk);
+                let _ = output.send(Ok(Output::Stderr(chunk))).await;
+            }
+            Err(error) => return Ok(Err(BackendError::Execution(error.to_string()))),
         }
     }
+
+    Ok(Ok(stderr))
 }
 
-impl FastCgiClient {
-    async fn connect_to_server(socket: &str) -> UnixStream {
-        let timeout = Duration::from_secs(5);
-        let mut interval = interval(Duration::from_millis(10));
-        let start_time = Instant::now();
-
-        loop {
-            tokio::select! {
-                _ = interval.tick() => {
-                    info!("Attempting to connect to {}...", socket);
-
-                    if let Ok(stream) = UnixStream::connect(socket).await {
-                        info!("Successfully connected to {}", socket);
-                        return stream;
-                    }
-
-                    if start_time.elapsed() > timeout {
-                        panic!("Failed to connect to {}", socket);
-                    }
-                }
+async fn connect_to_server(socket: &str) -> io::Result<UnixStream> {
+    let timeout = Duration::from_secs(5);
+    let mut interval = interval(Duration::from_millis(10));
+    let start_time = Instant::now();
+
+    info!("Attempting to connect to {}...", socket);
+
+    loop {
+        interval.tick().await;
+
+        match UnixStream::connect(socket).await {
+            Ok(stream) => {
+                info!("Successfully connected to {}", socket);
+                return Ok(stream);
             }
+            Err(error) if start_time.elapsed() > timeout => return Err(error),
+            Err(_) => continue,
         }
     }
 }
@@ -0,0 +1,49 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A document root in a temporary directory for tests, removed on drop.
+pub struct DocumentRoot(PathBuf);
+
+impl DocumentRoot {
+    /// An empty directory. `name` must be unique among the tests, which run
+    /// at the same time.
+    pub fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("sigan-{}-{name}", std::process::id()));
+
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+
+        Self(root)
+    }
+
+    /// Adds a file, with the directories leading to it.
+    pub fn file(self, path: &str, contents: impl AsRef<[u8]>) -> Self {
+        let file = self.join(path);
+
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(file, contents).unwrap();
+
+        self
+    }
+
+    /// Adds an empty directory.
+    pub fn dir(self, path: &str) -> Self {
+        fs::create_dir_all(self.join(path)).unwrap();
+
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: &str) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for DocumentRoot {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
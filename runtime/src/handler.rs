@@ -1,44 +1,287 @@
-use crate::context::RuntimeContext;
-use crate::fast_cgi::FastCgiClient;
-use lambda_http::{Body, Error, Request, RequestExt, Response};
-use regex_lite::Regex;
-
-pub async fn handler(mut req: Request, client: FastCgiClient) -> Result<Response<Body>, Error> {
-    let resp = Response::builder()
-        .status(200)
-        .header("content-type", "application/json")
-        .body(format!("{:#?}", req).into())
-        .map_err(Box::new)?;
+use crate::backend::{Backend, BackendError, BackendRequest};
+use crate::body::{self, ByteSize};
+use crate::cgi::{self, CgiParams};
+use crate::context::RuntimeExt;
+use crate::deadline;
+use crate::error_page;
+use crate::extension::PostInvoke;
+use crate::health::HealthCheck;
+use crate::logging;
+use crate::php_error;
+use crate::proxy::ProxyPolicy;
+use crate::response::{from_cgi_output, parse_head};
+use crate::rewrite::{Rewrite, RewriteEngine, RewriteRequest};
+use crate::router::{self, Route, Router};
+use crate::rules::Rules;
+use crate::statics;
+use crate::streaming::{read_head, StreamingBody};
+use crate::uploads::Uploads;
+use lambda_http::http::header::LOCATION;
+use lambda_http::http::StatusCode;
+use lambda_http::{Body, Error, Request, Response};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::{error, Instrument, Span};
+
+/// Chunks of PHP output buffered between the backend and Lambda.
+const OUTPUT_BUFFER: usize = 16;
+
+/// What a request turns into after the rules and the router.
+enum Dispatch {
+    Respond(Response<Body>),
+    Execute(BackendRequest),
+}
+
+/// The site requests are handled for, shared by the handlers.
+pub struct Site {
+    pub backend: Backend,
+    pub router: Router,
+    pub rules: Rules,
+    pub rewrite: RewriteEngine,
+    pub uploads: Uploads,
+    pub health: Option<HealthCheck>,
+    pub post_max_size: ByteSize,
+    pub proxies: ProxyPolicy,
+}
+
+pub async fn handler(
+    req: Request,
+    site: &Site,
+    post_invoke: &PostInvoke,
+) -> Result<Response<Body>, Error> {
+    let span = logging::invocation_span(&req);
+
+    handle(req, site, post_invoke).instrument(span).await
+}
+
+async fn handle(
+    mut req: Request,
+    site: &Site,
+    post_invoke: &PostInvoke,
+) -> Result<Response<Body>, Error> {
+    let mut invocation = post_invoke.start(&req);
+
+    let request = match dispatch(&mut req, site).await? {
+        Dispatch::Respond(response) => return Ok(response),
+        Dispatch::Execute(request) => request,
+    };
+
+    let response = match deadline::execute(&site.backend, request).await {
+        Err(BackendError::Timeout) => return status(StatusCode::GATEWAY_TIMEOUT),
+        Err(BackendError::Unavailable) => return status(StatusCode::SERVICE_UNAVAILABLE),
+        response => response?,
+    };
+
+    let fatal = php_error::log_stderr(&response.stderr);
+
+    // Lambda may freeze the environment once the response is sent, so files
+    // written by PHP are synced before, unless an extension holds it off.
+    post_invoke.executed(&mut invocation).await;
+
+    let response = from_cgi_output(response.stdout)?;
+
+    // What PHP sent before dying is cut short, unless it already answered
+    // with an error page of its own, like WordPress does.
+    if fatal && !response.status().is_server_error() {
+        return status(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    Ok(response)
+}
+
+/// Like `handler`, but responds as soon as PHP sent the headers, and streams
+/// the body while PHP writes it.
+pub async fn streaming_handler(
+    req: Request,
+    site: Arc<Site>,
+    post_invoke: Arc<PostInvoke>,
+) -> Result<Response<StreamingBody>, Error> {
+    let span = logging::invocation_span(&req);
+
+    handle_streaming(req, site, post_invoke)
+        .instrument(span)
+        .await
+}
+
+async fn handle_streaming(
+    mut req: Request,
+    site: Arc<Site>,
+    post_invoke: Arc<PostInvoke>,
+) -> Result<Response<StreamingBody>, Error> {
+    let mut invocation = post_invoke.start(&req);
+
+    let request = match dispatch(&mut req, &site).await? {
+        Dispatch::Respond(response) => return Ok(response.map(StreamingBody::from)),
+        Dispatch::Execute(request) => request,
+    };
+
+    let (sender, mut receiver) = mpsc::channel(OUTPUT_BUFFER);
 
-    let path = req.raw_http_path();
+    // PHP keeps writing the body after the response is returned, and its
+    // lines still belong to the invocation.
+    tokio::spawn(
+        async move {
+            match deadline::execute_stream(&site.backend, request, &sender).await {
+                // Fatal errors before the headers are caught along them,
+                // later ones can only be logged.
+                Ok(stderr) => {
+                    php_error::log_stderr(&stderr);
+                }
+                Err(error) => {
+                    error!("Failed to execute the PHP request: {}", error);
+                    let _ = sender.send(Err(error)).await;
+                }
+            }
 
-    /* Return 403 when trying to access protected files. */
+            // The response ends when the sender is dropped, so files are synced
+            // before, like in `handler`.
+            post_invoke.executed(&mut invocation).await;
+        }
+        .instrument(Span::current()),
+    );
 
-    if Regex::new(r"^/(?:wp-content|wp-includes)/.*\.php$")?.is_match(path) {
-        return access_forbidden();
+    // Past the headers, a timeout can only cut the body short.
+    let head = match read_head(&mut receiver).await {
+        Err(BackendError::Timeout) => {
+            return Ok(status(StatusCode::GATEWAY_TIMEOUT)?.map(StreamingBody::from))
+        }
+        head => head?,
+    };
+
+    let response = parse_head(&head.headers)?.body(StreamingBody::output(head.body, receiver))?;
+
+    // PHP died before sending the headers, answered like in `handler`.
+    if php_error::has_fatal(&head.stderr) && !response.status().is_server_error() {
+        return Ok(status(StatusCode::INTERNAL_SERVER_ERROR)?.map(StreamingBody::from));
+    }
+
+    Ok(response)
+}
+
+// Applies the access rules, the rewrites and the routes. Requests for scripts
+// are translated for PHP, everything else is answered right away.
+async fn dispatch(req: &mut Request, site: &Site) -> Result<Dispatch, Error> {
+    let Site {
+        backend,
+        router,
+        rules,
+        rewrite,
+        uploads,
+        health,
+        post_max_size,
+        proxies,
+    } = site;
+
+    // Everything after reads forwarded headers the policy trusts.
+    proxies.normalize(req);
+
+    // Rules, rewrites and routes all see the same canonical path.
+    let Some(request_path) = router::normalize_path(cgi::request_path(req)) else {
+        return access_denied(StatusCode::FORBIDDEN);
+    };
+
+    // The health check is reserved to the runtime, ahead of the site.
+    if let Some(health) = health
+        .as_ref()
+        .filter(|_| request_path == HealthCheck::PATH)
+    {
+        return Ok(Dispatch::Respond(health.respond(req, backend).await));
+    }
+
+    if let Some(status) = rules.check(req, &request_path) {
+        return access_denied(status);
+    }
+
+    if body::is_too_large(req, *post_max_size) {
+        return access_denied(StatusCode::PAYLOAD_TOO_LARGE);
     }
 
-    if Regex::new(r"\.(?:crt|ini|htaccess|json|scss)$")?.is_match(path) {
-        return access_forbidden();
+    let query = req.uri().query().unwrap_or_default();
+
+    let rewrite_request = RewriteRequest {
+        path: &request_path,
+        query,
+        method: req.method(),
+        headers: req.headers(),
+        https: cgi::scheme(req) == "https",
+    };
+
+    let (path, rewritten_query) = match rewrite.rewrite(&rewrite_request, router) {
+        Rewrite::Pass { path, query } => (path, query),
+        Rewrite::Redirect { status, location } => {
+            // Site paths are under the stage of API Gateway for clients.
+            let location = match location.starts_with('/') {
+                true => format!("{}{location}", cgi::path_prefix(req)),
+                false => location,
+            };
+
+            return redirect(status, &location);
+        }
+        Rewrite::Forbidden => return access_denied(StatusCode::FORBIDDEN),
+    };
+
+    // The rules also apply to where requests are rewritten to.
+    if path != request_path {
+        if let Some(status) = rules.check(req, &path) {
+            return access_denied(status);
+        }
     }
 
-    let document_root = std::env::var("WORDPRESS_ROOT").unwrap_or("/mnt/wordpress".into());
+    let (script_name, path_info) = match router.route(&path) {
+        Route::Script(script_name) => (script_name, None),
+        Route::PathInfo {
+            script_name,
+            path_info,
+        } => {
+            // Rules denying scripts must not be dodged by a path after them,
+            // like `/wp-content/uploads/shell.php/image.jpg`.
+            if let Some(status) = rules.check(req, &script_name) {
+                return access_denied(status);
+            }
+
+            (script_name, Some(path_info))
+        }
+        Route::Static(local_path) => match uploads.redirect(&path) {
+            Some(response) => return Ok(Dispatch::Respond(response)),
+            None => {
+                return statics::serve(req, &local_path)
+                    .await
+                    .map(Dispatch::Respond)
+            }
+        },
+        Route::Forbidden => return access_denied(StatusCode::FORBIDDEN),
+    };
 
-    let runtime_context = RuntimeContext::new("", document_root.as_str());
+    let context = router.context(req, &script_name, path_info.as_deref());
 
-    let response = client.send("/index.php", req).await;
+    let mut params = CgiParams::from_context(req, &context);
 
-    println!("Response: {:#?}", response);
+    // `REQUEST_URI` stays the original one, like with Apache.
+    if rewritten_query != query {
+        params.insert("QUERY_STRING", rewritten_query);
+    }
 
-    Ok(resp)
+    // Later stages read the context from the request.
+    req.set_runtime_context(context);
+
+    Ok(Dispatch::Execute(BackendRequest::from_request(req, params)))
 }
 
-fn access_forbidden() -> Result<Response<Body>, Error> {
+fn redirect(status: StatusCode, location: &str) -> Result<Dispatch, Error> {
     let response = Response::builder()
-        .status(403)
-        .header("content-type", "text/html")
-        .body("403 Forbidden".into())
+        .status(status)
+        .header(LOCATION, location)
+        .body(Body::Empty)
         .map_err(Box::new)?;
 
-    Ok(response)
+    Ok(Dispatch::Respond(response))
+}
+
+fn access_denied(status: StatusCode) -> Result<Dispatch, Error> {
+    self::status(status).map(Dispatch::Respond)
+}
+
+// Statuses the runtime answers with itself get an error page.
+fn status(status: StatusCode) -> Result<Response<Body>, Error> {
+    Ok(error_page::response(status))
 }
@@ -0,0 +1,349 @@
+use crate::backend::{Backend, BackendRequest};
+use crate::cgi::CgiParams;
+use crate::config::RuntimeConfig;
+use crate::deadline;
+use crate::error_page;
+use crate::response::from_cgi_output;
+use crate::util::constant_time_eq;
+use lambda_http::http::header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE};
+use lambda_http::http::StatusCode;
+use lambda_http::{Body, Request, Response};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+/// The script run through the backend, reporting the PHP version and the
+/// loaded extensions.
+const PHP_SCRIPT: &str = r#"<?php
+header('Content-Type: application/json');
+echo json_encode(['version' => PHP_VERSION, 'extensions' => get_loaded_extensions()]);
+"#;
+
+/// Headers proxies in front of Lambda are expected to set.
+const FORWARDED_HEADERS: [&str; 5] = [
+    "x-forwarded-for",
+    "x-forwarded-proto",
+    "x-forwarded-host",
+    "x-forwarded-port",
+    "cloudfront-forwarded-proto",
+];
+
+/// A reserved route reporting whether the runtime and what it depends on
+/// work, as JSON. Disabled unless a token is set.
+#[derive(Debug)]
+pub struct HealthCheck {
+    token: String,
+    database: Option<String>,
+    outbound_url: String,
+    script: PathBuf,
+}
+
+impl HealthCheck {
+    pub const PATH: &'static str = "/__sigan/health";
+    const TIMEOUT: Duration = Duration::from_secs(3);
+    pub const OUTBOUND_URL: &'static str = "https://example.com";
+
+    /// The check set in `config`, `None` to keep the route disabled without
+    /// a `health_token`.
+    ///
+    /// The PHP script is written once here, so concurrent checks don't
+    /// rewrite it while PHP reads it.
+    pub fn from_config(config: &RuntimeConfig) -> io::Result<Option<Self>> {
+        let Some(token) = config.health_token.clone() else {
+            return Ok(None);
+        };
+
+        let script = std::env::temp_dir().join(format!("sigan-health-{}.php", process::id()));
+        std::fs::write(&script, PHP_SCRIPT)?;
+
+        Ok(Some(Self {
+            token,
+            database: config.db_host.clone(),
+            outbound_url: config.health_outbound_url.clone(),
+            script,
+        }))
+    }
+
+    /// Answers requests with the token in `X-Sigan-Token` or as a bearer
+    /// token. Without it, the route doesn't exist.
+    pub async fn respond(&self, req: &Request, backend: &Backend) -> Response<Body> {
+        if !self.authorized(req) {
+            return error_page::response(StatusCode::NOT_FOUND);
+        }
+
+        let (backend_report, php, database, outbound) = tokio::join!(
+            check_backend(backend),
+            self.check_php(backend),
+            check_optional(self.database.as_deref(), check_database),
+            check_outbound(&self.outbound_url),
+        );
+
+        let healthy = [&backend_report, &php, &database, &outbound]
+            .iter()
+            .all(|report| report["status"] != "error");
+
+        let report = json!({
+            "status": if healthy { "ok" } else { "error" },
+            "backend": backend_report,
+            "php": php,
+            "database": database,
+            "outbound": outbound,
+            "forwardedHeaders": forwarded_headers(req),
+        });
+
+        Response::builder()
+            .status(match healthy {
+                true => StatusCode::OK,
+                false => StatusCode::SERVICE_UNAVAILABLE,
+            })
+            .header(CONTENT_TYPE, "application/json")
+            .header(CACHE_CONTROL, "no-store")
+            .body(Body::from(report.to_string()))
+            .unwrap_or_else(|_| error_page::response(StatusCode::INTERNAL_SERVER_ERROR))
+    }
+
+    fn authorized(&self, req: &Request) -> bool {
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+
+        let token = header("x-sigan-token")
+            .or(header(AUTHORIZATION.as_str()).and_then(|value| value.strip_prefix("Bearer ")));
+
+        token.is_some_and(|token| constant_time_eq(token.as_bytes(), self.token.as_bytes()))
+    }
+
+    async fn check_php(&self, backend: &Backend) -> Value {
+        let script_filename = self.script.to_string_lossy();
+
+        let params = CgiParams::from_iter([
+            ("GATEWAY_INTERFACE", "CGI/1.1"),
+            ("SERVER_PROTOCOL", "HTTP/1.1"),
+            ("REQUEST_METHOD", "GET"),
+            ("REQUEST_URI", HealthCheck::PATH),
+            ("SCRIPT_NAME", HealthCheck::PATH),
+            ("SCRIPT_FILENAME", &script_filename),
+        ]);
+
+        let request = BackendRequest {
+            params,
+            ..Default::default()
+        };
+
+        // A PHP stuck on the check is stopped, like requests past their
+        // deadline.
+        let output = match deadline::execute_for(backend, request, Self::TIMEOUT).await {
+            Ok(output) => output,
+            Err(error) => return failure(error),
+        };
+
+        let response = match from_cgi_output(output.stdout) {
+            Ok(response) => response,
+            Err(error) => return failure(error),
+        };
+
+        match serde_json::from_slice::<Value>(response.body()) {
+            Ok(info) => json!({
+                "status": "ok",
+                "version": info["version"],
+                "extensions": info["extensions"],
+            }),
+            Err(error) => failure(error),
+        }
+    }
+}
+
+async fn check_backend(backend: &Backend) -> Value {
+    match backend.health().await {
+        Ok(()) => json!({ "status": "ok", "kind": backend.kind().to_string() }),
+        Err(error) => failure(error),
+    }
+}
+
+async fn check_optional<'a, F>(target: Option<&'a str>, check: impl FnOnce(&'a str) -> F) -> Value
+where
+    F: Future<Output = Value>,
+{
+    match target {
+        Some(target) => check(target).await,
+        None => json!({ "status": "skipped" }),
+    }
+}
+
+/// Connects to MySQL at `host`, like `localhost:3306` in `DB_HOST`, and reads
+/// the version from the handshake the server starts with.
+async fn check_database(host: &str) -> Value {
+    let address = match host.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => host.to_string(),
+        _ => format!("{host}:3306"),
+    };
+
+    let started = Instant::now();
+
+    let handshake = tokio::time::timeout(HealthCheck::TIMEOUT, async {
+        let mut stream = TcpStream::connect(&address).await?;
+
+        // A 4 bytes packet header, then the payload.
+        let mut header = [0; 4];
+        stream.read_exact(&mut header).await?;
+
+        let length = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+        let mut payload = vec![0; length.min(1024)];
+        stream.read_exact(&mut payload).await?;
+
+        Ok::<_, std::io::Error>(payload)
+    })
+    .await;
+
+    let payload = match handshake {
+        Ok(Ok(payload)) => payload,
+        Ok(Err(error)) => return failure(error),
+        Err(_) => return failure("timed out"),
+    };
+
+    let latency = started.elapsed().as_millis() as u64;
+
+    // Protocol version 10, then the server version ending with a NUL byte.
+    match payload.split_first() {
+        Some((10, rest)) => {
+            let version = rest.split(|byte| *byte == 0).next().unwrap_or_default();
+
+            json!({
+                "status": "ok",
+                "address": address,
+                "serverVersion": String::from_utf8_lossy(version),
+                "latencyMs": latency,
+            })
+        }
+        Some((0xff, _)) => failure("the server refused the connection"),
+        _ => failure("not a MySQL server"),
+    }
+}
+
+async fn check_outbound(url: &str) -> Value {
+    let started = Instant::now();
+
+    let client = match reqwest::Client::builder()
+        .timeout(HealthCheck::TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(error) => return failure(error),
+    };
+
+    match client.get(url).send().await {
+        Ok(response) => json!({
+            "status": "ok",
+            "url": url,
+            "responseStatus": response.status().as_u16(),
+            "latencyMs": started.elapsed().as_millis() as u64,
+        }),
+        Err(error) => failure(error),
+    }
+}
+
+fn forwarded_headers(req: &Request) -> Value {
+    FORWARDED_HEADERS
+        .iter()
+        .map(|name| (name.to_string(), req.headers().contains_key(*name).into()))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+fn failure(error: impl ToString) -> Value {
+    json!({ "status": "error", "error": error.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    fn health_check() -> HealthCheck {
+        HealthCheck {
+            token: "s3cr3t".into(),
+            database: None,
+            outbound_url: HealthCheck::OUTBOUND_URL.into(),
+            script: PathBuf::from("/tmp/sigan-health.php"),
+        }
+    }
+
+    fn request(headers: &[(&'static str, &str)]) -> Request {
+        let mut req = Request::default();
+
+        for (name, value) in headers {
+            req.headers_mut().insert(*name, value.parse().unwrap());
+        }
+
+        req
+    }
+
+    #[test]
+    fn requires_the_token() {
+        let health_check = health_check();
+
+        assert!(health_check.authorized(&request(&[("x-sigan-token", "s3cr3t")])));
+        assert!(health_check.authorized(&request(&[("authorization", "Bearer s3cr3t")])));
+        assert!(!health_check.authorized(&request(&[("x-sigan-token", "s3cr3")])));
+        assert!(!health_check.authorized(&request(&[("authorization", "s3cr3t")])));
+        assert!(!health_check.authorized(&request(&[])));
+    }
+
+    #[test]
+    fn reports_forwarded_headers() {
+        let report = forwarded_headers(&request(&[
+            ("x-forwarded-for", "203.0.113.20"),
+            ("x-forwarded-proto", "https"),
+        ]));
+
+        assert_eq!(report["x-forwarded-for"], true);
+        assert_eq!(report["x-forwarded-proto"], true);
+        assert_eq!(report["x-forwarded-host"], false);
+    }
+
+    #[tokio::test]
+    async fn reads_the_mysql_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut payload = vec![10];
+            payload.extend_from_slice(b"8.0.36\0");
+            payload.extend_from_slice(&[0; 16]);
+
+            let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+            packet.push(0);
+            packet.extend_from_slice(&payload);
+
+            stream.write_all(&packet).await.unwrap();
+        });
+
+        let report = check_database(&address).await;
+
+        assert_eq!(report["status"], "ok");
+        assert_eq!(report["serverVersion"], "8.0.36");
+    }
+
+    #[tokio::test]
+    async fn reports_unreachable_databases() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        assert_eq!(check_database(&address).await["status"], "error");
+        assert_eq!(
+            check_optional(None, check_database).await["status"],
+            "skipped"
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use lambda_http::{Request, RequestExt};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{info_span, Event, Span, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+
+#[derive(Debug, Error)]
+pub enum LoggingError {
+    #[error("Unknown log format `{0}`, expected one of: text, json")]
+    UnknownFormat(String),
+}
+
+/// How log lines are written to stdout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Colored text, for local development.
+    #[default]
+    Text,
+    /// A JSON object per line, with the invocation it belongs to, for
+    /// CloudWatch.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = LoggingError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(LoggingError::UnknownFormat(value.into())),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Text => write!(f, "text"),
+            Self::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Sets up the global subscriber.
+pub fn init(format: LogFormat) {
+    let layer = tracing_subscriber::fmt::layer()
+        .without_time()
+        .with_target(false);
+
+    let layer = match format {
+        LogFormat::Text => layer.with_ansi(true).boxed(),
+        LogFormat::Json => layer.with_ansi(false).event_format(JsonFormat).boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(InvocationLayer)
+        .with(layer)
+        .init();
+}
+
+const INVOCATION_SPAN: &str = "invocation";
+
+/// Span tagging the lines logged while handling `req` with its invocation.
+/// Requests are handled concurrently in serve mode, so each one carries its
+/// own. Outside of Lambda, there is no invocation to tag lines with.
+pub fn invocation_span(req: &Request) -> Span {
+    match req.lambda_context_ref() {
+        Some(context) => info_span!(
+            INVOCATION_SPAN,
+            request_id = context.request_id.as_str(),
+            trace_id = context.xray_trace_id.as_deref(),
+        ),
+        None => Span::none(),
+    }
+}
+
+/// The invocation an invocation span was opened for.
+#[derive(Clone, Debug, Default)]
+struct Invocation {
+    request_id: Option<String>,
+    trace_id: Option<String>,
+}
+
+impl Visit for Invocation {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "request_id" => self.request_id = Some(value.into()),
+            "trace_id" => self.trace_id = Some(value.into()),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn fmt::Debug) {}
+}
+
+/// Keeps the fields of invocation spans, for `JsonFormat` to find them.
+struct InvocationLayer;
+
+impl<S> Layer<S> for InvocationLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, context: Context<'_, S>) {
+        if attributes.metadata().name() != INVOCATION_SPAN {
+            return;
+        }
+
+        let mut invocation = Invocation::default();
+        attributes.record(&mut invocation);
+
+        if let Some(span) = context.span(id) {
+            span.extensions_mut().insert(invocation);
+        }
+    }
+}
+
+/// Writes events as JSON objects, with the level, the target, the invocation
+/// and the event fields.
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        context: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut line = Map::new();
+
+        line.insert("level".into(), metadata.level().as_str().into());
+        line.insert("target".into(), metadata.target().into());
+
+        let invocation = context
+            .event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .find_map(|span| span.extensions().get::<Invocation>().cloned());
+
+        if let Some(invocation) = invocation {
+            if let Some(request_id) = invocation.request_id {
+                line.insert("requestId".into(), request_id.into());
+            }
+
+            if let Some(trace_id) = invocation.trace_id {
+                line.insert("traceId".into(), trace_id.into());
+            }
+        }
+
+        event.record(&mut JsonFields(&mut line));
+
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+struct JsonFields<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonFields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{value:?}").into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tracing::{error, info};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn invocation(request_id: &str, trace_id: Option<&str>) -> Request {
+        let mut context = lambda_http::Context::default();
+        context.request_id = request_id.into();
+        context.xray_trace_id = trace_id.map(Into::into);
+
+        Request::default().with_lambda_context(context)
+    }
+
+    #[test]
+    fn writes_json_lines_with_the_invocation() {
+        let buffer = Buffer::default();
+
+        let subscriber = tracing_subscriber::registry().with(InvocationLayer).with(
+            tracing_subscriber::fmt::layer()
+                .with_writer({
+                    let buffer = buffer.clone();
+                    move || buffer.clone()
+                })
+                .event_format(JsonFormat),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            let first = invocation_span(&invocation(
+                "8f5f0c33",
+                Some("Root=1-65f1a2b3-0123456789abcdef01234567"),
+            ));
+            let second = invocation_span(&invocation("1d4e9a70", None));
+
+            // Requests handled at the same time each log with their own.
+            first.in_scope(|| info!("Synced {} files", 2));
+            second.in_scope(|| error!(status = 500, "PHP Fatal error:\n  thrown in index.php"));
+            info!("Shutting down");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 3);
+
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["target"], "runtime::logging::tests");
+        assert_eq!(lines[0]["requestId"], "8f5f0c33");
+        assert_eq!(
+            lines[0]["traceId"],
+            "Root=1-65f1a2b3-0123456789abcdef01234567"
+        );
+        assert_eq!(lines[0]["message"], "Synced 2 files");
+
+        assert_eq!(lines[1]["level"], "ERROR");
+        assert_eq!(lines[1]["requestId"], "1d4e9a70");
+        assert_eq!(lines[1]["traceId"], Value::Null);
+        assert_eq!(lines[1]["status"], 500);
+        assert_eq!(
+            lines[1]["message"],
+            "PHP Fatal error:\n  thrown in index.php"
+        );
+
+        assert_eq!(lines[2]["requestId"], Value::Null);
+    }
+
+    #[test]
+    fn skips_requests_outside_of_lambda() {
+        assert!(invocation_span(&Request::default()).is_none());
+    }
+
+    #[test]
+    fn parses_formats() {
+        assert_eq!("JSON".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!("text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert!("logfmt".parse::<LogFormat>().is_err());
+    }
+}
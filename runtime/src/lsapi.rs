@@ -0,0 +1,140 @@
+use crate::backend::{BackendError, BackendRequest, BackendResponse, PhpBackend};
+use crate::config::RuntimeConfig;
+use crate::process::{prepare_socket, PhpProcess};
+use litespeed_client::{Client, Request};
+
+/// Runs PHP through a `lsphp` process speaking the LiteSpeed SAPI protocol.
+pub struct LsapiBackend {
+    process: PhpProcess,
+    client: Client,
+}
+
+impl LsapiBackend {
+    const COMMAND: &'static str = "lsphp";
+}
+
+impl PhpBackend for LsapiBackend {
+    async fn start(config: &RuntimeConfig) -> Result<Self, BackendError> {
+        let socket = &config.lsapi_socket;
+        let php_ini = config.php_ini.to_string_lossy();
+
+        prepare_socket(socket).map_err(BackendError::Process)?;
+
+        let overrides = config.php_ini_overrides();
+        let mut args = vec!["-b", socket, "-c", &php_ini];
+        args.extend(overrides.iter().map(String::as_str));
+
+        let process = PhpProcess::spawn(Self::COMMAND, &args).map_err(BackendError::Process)?;
+
+        let client = Client::new(socket)
+            .await
+            .map_err(BackendError::Connection)?;
+
+        Ok(Self { process, client })
+    }
+
+    async fn execute(&self, request: BackendRequest) -> Result<BackendResponse, BackendError> {
+        // HTTP headers travel in their own section of the LSAPI packet.
+        let headers: Vec<(String, &str)> = request
+            .params
+            .iter()
+            .filter_map(|(name, value)| header_name(name).map(|name| (name, value)))
+            .collect();
+
+        let param = |name: &str| request.params.get(name).unwrap_or_default();
+
+        let mut lsapi_request = Request::new()
+            .script_filename(param("SCRIPT_FILENAME"))
+            .script_name(param("SCRIPT_NAME"))
+            .query_string(param("QUERY_STRING"))
+            .request_method(param("REQUEST_METHOD"));
+
+        for (name, value) in request.params.iter() {
+            lsapi_request = match name {
+                // Required variables, already set above.
+                "SCRIPT_FILENAME" | "SCRIPT_NAME" | "QUERY_STRING" | "REQUEST_METHOD" => {
+                    lsapi_request
+                }
+                "PATH_INFO" => lsapi_request.path_info(value),
+                "PATH_TRANSLATED" => lsapi_request.path_translated(value),
+                name if header_name(name).is_some() => lsapi_request,
+                name => lsapi_request.env_variable(name, value),
+            };
+        }
+
+        for (name, value) in &headers {
+            lsapi_request = lsapi_request.http_header(name, value);
+        }
+
+        let response = self
+            .client
+            .execute(lsapi_request, &request.body)
+            .await
+            .map_err(|error| BackendError::Execution(error.to_string()))?;
+
+        let (status, headers, body, stderr) = response.into_parts();
+
+        // Rebuilds the CGI output, so responses are parsed the same for every backend.
+        let mut stdout = format!("Status: {}\r\n", status).into_bytes();
+
+        for header in headers {
+            stdout.extend_from_slice(header.as_bytes());
+            stdout.extend_from_slice(b"\r\n");
+        }
+
+        stdout.extend_from_slice(b"\r\n");
+        stdout.extend_from_slice(&body);
+
+        Ok(BackendResponse { stdout, stderr })
+    }
+
+    async fn health(&self) -> Result<(), BackendError> {
+        match self.process.is_running().await {
+            true => Ok(()),
+            false => Err(BackendError::Unavailable),
+        }
+    }
+
+    async fn abort(&self) -> Result<(), BackendError> {
+        prepare_socket(self.client.address()).map_err(BackendError::Process)?;
+
+        self.process
+            .restart()
+            .await
+            .map_err(BackendError::Process)?;
+
+        self.client
+            .reconnect()
+            .await
+            .map_err(BackendError::Connection)
+    }
+
+    async fn shutdown(&self) -> Result<(), BackendError> {
+        self.process.kill().await.map_err(BackendError::Process)
+    }
+}
+
+// Maps `HTTP_ACCEPT_LANGUAGE` to `Accept-Language`.
+fn header_name(param: &str) -> Option<String> {
+    let name = match param {
+        "CONTENT_TYPE" | "CONTENT_LENGTH" => param,
+        _ => param.strip_prefix("HTTP_")?,
+    };
+
+    let name = name
+        .split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first
+                    .to_uppercase()
+                    .chain(chars.flat_map(char::to_lowercase))
+                    .collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("-");
+
+    Some(name)
+}
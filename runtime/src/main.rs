@@ -1,55 +1,328 @@
-use lambda_http::{run, service_fn};
-use std::fs;
-use std::path::Path;
-use tracing::{info, Level};
+#[cfg(not(any(feature = "lsapi", feature = "fastcgi", feature = "embed")))]
+compile_error!("At least one PHP backend feature must be enabled: lsapi, fastcgi or embed.");
+
+mod backend;
+mod body;
+mod cgi;
+mod config;
+mod context;
+mod deadline;
+#[cfg(feature = "embed")]
+mod embed;
+mod error_page;
+mod extension;
+#[cfg(feature = "fastcgi")]
+mod fast_cgi;
+#[cfg(test)]
+mod fixture;
+mod handler;
+mod health;
+mod logging;
+#[cfg(feature = "lsapi")]
+mod lsapi;
+mod multisite;
+mod php_error;
+#[cfg(any(feature = "lsapi", feature = "fastcgi"))]
+mod process;
+mod proxy;
+mod response;
+mod rewrite;
+mod router;
+mod rules;
+mod s3;
+mod serve;
+mod statics;
+mod streaming;
+mod sync;
+mod uploads;
+mod util;
+mod warmup;
+
+use backend::Backend;
+use config::RuntimeConfig;
+use error_page::ErrorPages;
+use extension::{flush_logs, Extension, PostInvoke};
+use handler::{handler, streaming_handler, Site};
+use health::HealthCheck;
+use lambda_http::{run, run_with_streaming_response, service_fn, Error};
+use proxy::ProxyPolicy;
+use rewrite::RewriteEngine;
+use router::Router;
+use rules::Rules;
+use s3::Credentials;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+use streaming::{ResponseMode, StreamingBody};
+use sync::UploadsSync;
+use tracing::{error, info};
+use uploads::Uploads;
+use warmup::{InitType, Warmup};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // Get environment variables.
+    let started = Instant::now();
+
+    // `serve` runs a local HTTP server instead of waiting for Lambda events.
+
+    let serve = std::env::args()
+        .nth(1)
+        .is_some_and(|command| command == "serve");
 
-    let host_port = std::env::var("HOST_PORT").unwrap_or(3000.to_string());
+    // Load the configuration, from the environment and the config file.
+
+    let config = RuntimeConfig::from_env()?;
 
     // Set up tracing.
 
-    tracing_subscriber::fmt()
-        .with_max_level(Level::INFO)
-        .without_time()
-        .with_target(false)
-        .with_ansi(true)
-        .init();
+    logging::init(config.log_format);
+
+    // Start PHP backend.
+
+    let backend = Backend::start(&config).await?;
+
+    backend.health().await?;
+
+    info!("Started {} PHP backend", backend.kind());
+
+    // Start server.
+
+    // Networks use the rewrites of their `.htaccess` when they ship one, and
+    // the built-in ones otherwise. Never both, as both match the same URLs.
+
+    let rewrite = match (
+        config.multisite,
+        RewriteEngine::from_document_root(&config.document_root)?,
+    ) {
+        (Some(multisite), Some(rewrite)) => {
+            info!(
+                "Serving a {} multisite network with the rewrites of .htaccess",
+                multisite
+            );
+            rewrite
+        }
+        (Some(multisite), None) => {
+            info!(
+                "Serving a {} multisite network with the built-in rewrites",
+                multisite
+            );
+            multisite.rewrite_engine()
+        }
+        (None, rewrite) => rewrite.unwrap_or_default(),
+    };
 
-    // Create socket file.
+    info!("Loaded {} rewrite rules", rewrite.len());
 
-    info!("Creating a socket...");
-    let socket_path = Path::new("/tmp/lsphp.sock");
+    let router = Router::new(&config.document_root);
+    let rules = Rules::from_file(&config.rules_file)?;
 
-    if socket_path.exists() {
-        info!("Socket already exist");
-        fs::remove_file(socket_path).expect("Failed to remove socket");
-        info!("Existing socket removed");
+    info!("Loaded {} access rules", rules.len());
+
+    let credentials = Credentials::from_env();
+
+    let uploads = Uploads::from_config(&config, credentials.clone())?;
+
+    let uploads_sync = UploadsSync::from_config(&config, credentials).await?;
+
+    info!("Serving uploads in {} mode", uploads.mode());
+
+    let error_pages = Arc::new(ErrorPages::load(&config.error_pages_dir)?);
+
+    info!("Loaded {} error page templates", error_pages.len());
+
+    let health = HealthCheck::from_config(&config)?;
+
+    if health.is_some() {
+        info!("Health check enabled at {}", HealthCheck::PATH);
     }
 
-    if let Some(parent) = socket_path.parent() {
-        fs::create_dir_all(parent).expect("Failed to create socket parent path");
+    let site = Arc::new(Site {
+        backend,
+        router,
+        rules,
+        rewrite,
+        uploads,
+        health,
+        post_max_size: config.post_max_size,
+        proxies: ProxyPolicy::new(config.trusted_proxy, config.proxy_secret.clone()),
+    });
+
+    // In Lambda, the runtime registers as an extension to run the post-invoke
+    // work after responses, and to get a SIGTERM before shutting down.
+
+    let extension = match serve {
+        true => None,
+        false => Extension::register_from_env().await?,
+    };
+
+    let (post_invoke, invocations) = match extension {
+        Some(_) => {
+            let (post_invoke, invocations) = PostInvoke::deferred(uploads_sync);
+            (post_invoke, Some(invocations))
+        }
+        None => (PostInvoke::new(uploads_sync), None),
+    };
+
+    let post_invoke = Arc::new(post_invoke);
+
+    // Spawns a task to handle graceful shutdown: the last post-invoke work,
+    // stopping the PHP backend and flushing the logs. The guard holds the
+    // shutdown until it is done.
+
+    tokio::spawn({
+        let site = site.clone();
+        let post_invoke = post_invoke.clone();
+        let guard = elegant_departure::get_shutdown_guard();
+
+        async move {
+            guard.wait().await;
+
+            // Catches whatever invocations left behind, like ones whose
+            // report came too late.
+            post_invoke.run(SystemTime::UNIX_EPOCH).await;
+
+            if let Err(error) = site.backend.shutdown().await {
+                error!("Failed to shut down PHP backend: {}", error);
+            }
+
+            info!("Runtime shut down");
+            flush_logs();
+            drop(guard);
+        }
+    });
+
+    let response_mode = config.response_mode;
+
+    info!("Sending {} responses", response_mode);
+
+    // Warm PHP up before the first request, filling opcache. Warmup requests
+    // don't sync uploads.
+
+    let init_type = InitType::from_env();
+    let warmup = Warmup::from_config(&config, init_type);
+
+    if !warmup.is_empty() {
+        let warmup_started = Instant::now();
+        let post_invoke = PostInvoke::new(None);
+
+        let count = warmup
+            .run(
+                &site.backend,
+                &site.router.document_root().to_string_lossy(),
+                |req| handler(req, &site, &post_invoke),
+            )
+            .await;
+
+        info!(
+            "Warmed up with {} requests in {} ms",
+            count,
+            warmup_started.elapsed().as_millis()
+        );
     }
 
-    fs::File::create(socket_path).expect("Failed to create socket");
-    info!("Created new socket {:?}", socket_path);
+    info!(
+        "Initialized in {} ms ({} init)",
+        started.elapsed().as_millis(),
+        init_type
+    );
 
-    // Start server.
+    let server = async {
+        if serve {
+            let address = SocketAddr::from(([0, 0, 0, 0], config.port));
+
+            let handler = {
+                let site = site.clone();
+                let post_invoke = post_invoke.clone();
+
+                move |req| {
+                    let site = site.clone();
+                    let post_invoke = post_invoke.clone();
+
+                    async move {
+                        match response_mode {
+                            ResponseMode::Buffered => handler(req, &site, &post_invoke)
+                                .await
+                                .map(|response| response.map(StreamingBody::from)),
+                            ResponseMode::Streaming => {
+                                streaming_handler(req, site, post_invoke).await
+                            }
+                        }
+                    }
+                }
+            };
+
+            info!("Runtime listening to http://localhost:{}\n", config.port);
+
+            return serve::serve(address, error_pages.clone(), handler)
+                .await
+                .map_err(Into::into);
+        }
+
+        match response_mode {
+            ResponseMode::Buffered => {
+                run(service_fn(|req| {
+                    let site = site.clone();
+                    let post_invoke = post_invoke.clone();
+                    let error_pages = error_pages.clone();
+
+                    async move {
+                        let response =
+                            error_page::respond(&error_pages, req, move |req| async move {
+                                handler(req, &site, &post_invoke).await
+                            });
+
+                        Ok::<_, Error>(response.await)
+                    }
+                }))
+                .await
+            }
+            ResponseMode::Streaming => {
+                run_with_streaming_response(service_fn(|req| {
+                    let site = site.clone();
+                    let post_invoke = post_invoke.clone();
+                    let error_pages = error_pages.clone();
+
+                    async move {
+                        let response =
+                            error_page::respond(&error_pages, req, move |req| async move {
+                                streaming_handler(req, site, post_invoke).await
+                            });
+
+                        Ok::<_, Error>(response.await)
+                    }
+                }))
+                .await
+            }
+        }
+    };
 
-    let server = run(service_fn(|req| async move {
-        println!("AWS Request:\n{:#?}", req);
-        Result::<&str, std::convert::Infallible>::Ok("👋 world!")
-    }));
+    // The extension stops when Lambda shuts down, or fails. Either way the
+    // environment can't serve invocations anymore.
+    let extension = async {
+        match (extension, invocations) {
+            (Some(extension), Some(invocations)) => {
+                if let Err(error) = extension.run(&post_invoke, invocations).await {
+                    error!("Extension stopped: {}", error);
+                }
+            }
+            _ => std::future::pending().await,
+        }
+    };
 
     let shutdown_listener = elegant_departure::tokio::depart()
         .on_termination()
         .on_completion(async {
-            let _ = server.await;
+            tokio::select! {
+                result = server => {
+                    if let Err(error) = result {
+                        error!("Runtime stopped: {}", error);
+                    }
+                }
+                _ = extension => {}
+            }
         });
 
-    info!("Runtime listening to http://localhost:{host_port}\n");
+    shutdown_listener.await;
 
-    Ok(shutdown_listener.await)
+    Ok(())
 }
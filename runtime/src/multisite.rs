@@ -0,0 +1,187 @@
+use crate::rewrite::RewriteEngine;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MultisiteError {
+    #[error("Unknown multisite mode `{0}`, expected one of: subdirectory, subdomain")]
+    UnknownMode(String),
+}
+
+/// The rewrites WordPress asks for in the `.htaccess` of a subdirectory
+/// network, where sites live under `/{site}/`.
+const SUBDIRECTORY_RULES: &str = r#"
+RewriteEngine On
+RewriteBase /
+RewriteRule ^index\.php$ - [L]
+RewriteRule ^([_0-9a-zA-Z-]+/)?wp-admin$ $1wp-admin/ [R=301,L]
+RewriteCond %{REQUEST_FILENAME} -f [OR]
+RewriteCond %{REQUEST_FILENAME} -d
+RewriteRule ^ - [L]
+RewriteRule ^([_0-9a-zA-Z-]+/)?(wp-(content|admin|includes).*) $2 [L]
+RewriteRule ^([_0-9a-zA-Z-]+/)?(.*\.php)$ $2 [L]
+"#;
+
+/// The rewrites of a subdomain network, where sites are told apart by host.
+const SUBDOMAIN_RULES: &str = r#"
+RewriteEngine On
+RewriteBase /
+RewriteRule ^index\.php$ - [L]
+RewriteRule ^wp-admin$ wp-admin/ [R=301,L]
+"#;
+
+/// The kind of WordPress network served.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Multisite {
+    Subdirectory,
+    Subdomain,
+}
+
+impl Multisite {
+    /// Rewrites mapping the URLs of every site to the shared WordPress files.
+    /// Scripts end up with their real `SCRIPT_NAME`, while `REQUEST_URI`
+    /// keeps the site path WordPress looks the site up with.
+    pub fn rewrite_engine(self) -> RewriteEngine {
+        let rules = match self {
+            Self::Subdirectory => SUBDIRECTORY_RULES,
+            Self::Subdomain => SUBDOMAIN_RULES,
+        };
+
+        RewriteEngine::parse(rules).expect("multisite rules are valid")
+    }
+}
+
+impl FromStr for Multisite {
+    type Err = MultisiteError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "subdirectory" => Ok(Self::Subdirectory),
+            "subdomain" => Ok(Self::Subdomain),
+            _ => Err(MultisiteError::UnknownMode(value.into())),
+        }
+    }
+}
+
+impl fmt::Display for Multisite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Subdirectory => write!(f, "subdirectory"),
+            Self::Subdomain => write!(f, "subdomain"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::DocumentRoot;
+    use crate::rewrite::{Rewrite, RewriteRequest};
+    use crate::router::{Route, Router};
+    use lambda_http::http::{HeaderMap, Method, StatusCode};
+
+    fn wordpress(name: &str) -> DocumentRoot {
+        DocumentRoot::new(&format!("multisite-{name}"))
+            .file("index.php", "<?php")
+            .file("wp-login.php", "<?php")
+            .file("wp-admin/index.php", "<?php")
+            .file("wp-admin/options.php", "<?php")
+            .file("wp-content/themes/theme/style.css", "")
+    }
+
+    // Rewrites a URL path and routes the result, like the handler does.
+    fn route(multisite: Multisite, root: &DocumentRoot, path: &str) -> Result<Route, Rewrite> {
+        let router = Router::new(root.path());
+        let request = RewriteRequest {
+            path,
+            query: "",
+            method: &Method::GET,
+            headers: &HeaderMap::new(),
+            https: true,
+        };
+
+        match multisite.rewrite_engine().rewrite(&request, &router) {
+            Rewrite::Pass { path, .. } => Ok(router.route(&path)),
+            rewrite => Err(rewrite),
+        }
+    }
+
+    fn redirect(location: &str) -> Result<Route, Rewrite> {
+        Err(Rewrite::Redirect {
+            status: StatusCode::MOVED_PERMANENTLY,
+            location: location.into(),
+        })
+    }
+
+    fn script(script_name: &str) -> Result<Route, Rewrite> {
+        Ok(Route::Script(script_name.into()))
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!(
+            "Subdirectory".parse::<Multisite>().unwrap(),
+            Multisite::Subdirectory
+        );
+        assert_eq!(
+            "subdomain".parse::<Multisite>().unwrap(),
+            Multisite::Subdomain
+        );
+        assert!("subfolder".parse::<Multisite>().is_err());
+    }
+
+    #[test]
+    fn routes_subdirectory_sites() {
+        let root = wordpress("subdirectory");
+        let multisite = Multisite::Subdirectory;
+
+        assert_eq!(
+            route(multisite, &root, "/blog/wp-admin"),
+            redirect("/blog/wp-admin/")
+        );
+        assert_eq!(route(multisite, &root, "/wp-admin"), redirect("/wp-admin/"));
+        assert_eq!(
+            route(multisite, &root, "/blog/wp-admin/"),
+            script("/wp-admin/index.php")
+        );
+        assert_eq!(
+            route(multisite, &root, "/blog/wp-admin/options.php"),
+            script("/wp-admin/options.php")
+        );
+        assert_eq!(
+            route(multisite, &root, "/blog/wp-login.php"),
+            script("/wp-login.php")
+        );
+        assert_eq!(
+            route(multisite, &root, "/blog/wp-content/themes/theme/style.css"),
+            Ok(Route::Static(
+                root.join("wp-content/themes/theme/style.css")
+            ))
+        );
+        assert_eq!(
+            route(multisite, &root, "/blog/hello-world/"),
+            script("/index.php")
+        );
+        assert_eq!(
+            route(multisite, &root, "/wp-admin/options.php"),
+            script("/wp-admin/options.php")
+        );
+    }
+
+    #[test]
+    fn routes_subdomain_sites() {
+        let root = wordpress("subdomain");
+        let multisite = Multisite::Subdomain;
+
+        assert_eq!(route(multisite, &root, "/wp-admin"), redirect("/wp-admin/"));
+        assert_eq!(
+            route(multisite, &root, "/wp-admin/"),
+            script("/wp-admin/index.php")
+        );
+        assert_eq!(
+            route(multisite, &root, "/hello-world/"),
+            script("/index.php")
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use regex_lite::Regex;
+use std::fmt;
+use std::sync::OnceLock;
+use tracing::{error, info, warn};
+
+/// Severities PHP prints errors with, like `PHP Fatal error:`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Fatal,
+    RecoverableFatal,
+    Parse,
+    Warning,
+    Notice,
+    Deprecated,
+}
+
+impl Severity {
+    const LABELS: [(&'static str, Self); 6] = [
+        ("Fatal error", Self::Fatal),
+        ("Recoverable fatal error", Self::RecoverableFatal),
+        ("Parse error", Self::Parse),
+        ("Warning", Self::Warning),
+        ("Notice", Self::Notice),
+        ("Deprecated", Self::Deprecated),
+    ];
+
+    fn from_label(label: &str) -> Option<Self> {
+        Self::LABELS
+            .into_iter()
+            .find(|(known, _)| *known == label)
+            .map(|(_, severity)| severity)
+    }
+
+    /// Whether the error stopped the script.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Self::Fatal | Self::RecoverableFatal | Self::Parse)
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fatal => write!(f, "fatal"),
+            Self::RecoverableFatal => write!(f, "recoverable-fatal"),
+            Self::Parse => write!(f, "parse"),
+            Self::Warning => write!(f, "warning"),
+            Self::Notice => write!(f, "notice"),
+            Self::Deprecated => write!(f, "deprecated"),
+        }
+    }
+}
+
+/// An error as PHP writes it to stderr:
+/// `PHP Fatal error:  Uncaught Error: ... in /path/file.php on line 12`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PhpError {
+    pub severity: Severity,
+    pub message: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+impl PhpError {
+    /// Parses an error, spanning several lines for uncaught exceptions with
+    /// their stack trace.
+    pub fn parse(entry: &str) -> Option<Self> {
+        static PATTERN: OnceLock<Regex> = OnceLock::new();
+
+        let pattern = PATTERN.get_or_init(|| {
+            Regex::new(
+                r"(?s)^(?:\[[^\]\n]*\] )?(?:PHP )?([A-Za-z ]+?):\s+(.*?)(?: in ([^\n]+) on line (\d+))?\s*$",
+            )
+            .expect("PHP error pattern is valid")
+        });
+
+        let captures = pattern.captures(entry)?;
+
+        Some(Self {
+            severity: Severity::from_label(&captures[1])?,
+            message: captures[2].to_string(),
+            file: captures.get(3).map(|file| file.as_str().to_string()),
+            line: captures.get(4).and_then(|line| line.as_str().parse().ok()),
+        })
+    }
+}
+
+// Splits stderr into entries, each starting with a line PHP starts errors
+// with. Lines before the first error make an entry of their own.
+fn entries(stderr: &str) -> Vec<String> {
+    static START: OnceLock<Regex> = OnceLock::new();
+
+    let start = START.get_or_init(|| {
+        let labels = Severity::LABELS.map(|(label, _)| label).join("|");
+
+        Regex::new(&format!(r"^(?:\[[^\]]*\] )?(?:PHP )?(?:{labels}):"))
+            .expect("PHP error start pattern is valid")
+    });
+
+    let mut entries: Vec<String> = Vec::new();
+
+    for line in stderr.lines() {
+        match entries.last_mut() {
+            Some(entry) if !start.is_match(line) => {
+                entry.push('\n');
+                entry.push_str(line);
+            }
+            _ => entries.push(line.to_string()),
+        }
+    }
+
+    entries
+}
+
+/// Whether the error output of a request holds a fatal error, without logging
+/// it.
+pub fn has_fatal(stderr: &[u8]) -> bool {
+    entries(&String::from_utf8_lossy(stderr))
+        .iter()
+        .filter_map(|entry| PhpError::parse(entry))
+        .any(|php_error| php_error.severity.is_fatal())
+}
+
+/// Logs the error output of a request, with PHP errors as structured events.
+/// Returns whether one of them was fatal.
+pub fn log_stderr(stderr: &[u8]) -> bool {
+    let mut fatal = false;
+
+    for entry in entries(&String::from_utf8_lossy(stderr)) {
+        let php_error = match PhpError::parse(&entry) {
+            Some(php_error) => php_error,
+            None => {
+                error!("{}", entry);
+                continue;
+            }
+        };
+
+        let PhpError {
+            severity,
+            message,
+            file,
+            line,
+        } = &php_error;
+
+        match severity {
+            _ if severity.is_fatal() => {
+                fatal = true;
+                error!(%severity, file, line, "PHP error: {}", message);
+            }
+            Severity::Warning => warn!(%severity, file, line, "PHP error: {}", message),
+            _ => info!(%severity, file, line, "PHP error: {}", message),
+        }
+    }
+
+    fatal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_errors() {
+        assert_eq!(
+            PhpError::parse(
+                "PHP Warning:  Undefined variable $post in /mnt/wordpress/wp-content/themes/theme/single.php on line 12"
+            ),
+            Some(PhpError {
+                severity: Severity::Warning,
+                message: "Undefined variable $post".into(),
+                file: Some("/mnt/wordpress/wp-content/themes/theme/single.php".into()),
+                line: Some(12),
+            })
+        );
+
+        assert_eq!(
+            PhpError::parse("PHP Parse error:  syntax error, unexpected token \"}\" in /mnt/wordpress/index.php on line 3"),
+            Some(PhpError {
+                severity: Severity::Parse,
+                message: "syntax error, unexpected token \"}\"".into(),
+                file: Some("/mnt/wordpress/index.php".into()),
+                line: Some(3),
+            })
+        );
+
+        assert_eq!(PhpError::parse("Segmentation fault"), None);
+    }
+
+    #[test]
+    fn parses_uncaught_exceptions() {
+        let stderr = "\
+PHP Notice:  Function _load_textdomain_just_in_time was called incorrectly in /mnt/wordpress/wp-includes/functions.php on line 6114
+PHP Fatal error:  Uncaught Error: Call to undefined function get_header() in /mnt/wordpress/index.php:5
+Stack trace:
+#0 {main}
+  thrown in /mnt/wordpress/index.php on line 5
+";
+
+        let errors: Vec<PhpError> = entries(stderr)
+            .iter()
+            .map(|entry| PhpError::parse(entry).unwrap())
+            .collect();
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].severity, Severity::Notice);
+
+        assert_eq!(errors[1].severity, Severity::Fatal);
+        assert_eq!(
+            errors[1].message,
+            "Uncaught Error: Call to undefined function get_header() in /mnt/wordpress/index.php:5\nStack trace:\n#0 {main}\n  thrown"
+        );
+        assert_eq!(errors[1].file.as_deref(), Some("/mnt/wordpress/index.php"));
+        assert_eq!(errors[1].line, Some(5));
+    }
+
+    #[test]
+    fn tells_fatal_errors() {
+        let fatal: &[u8] = b"PHP Fatal error:  Allowed memory size of 134217728 bytes exhausted in /mnt/wordpress/wp-includes/class-wpdb.php on line 2349";
+        let deprecated: &[u8] = b"PHP Deprecated:  Creation of dynamic property is deprecated in /mnt/wordpress/wp-content/plugins/plugin/plugin.php on line 40\nplain output";
+
+        assert!(log_stderr(fatal));
+        assert!(has_fatal(fatal));
+        assert!(!log_stderr(deprecated));
+        assert!(!has_fatal(deprecated));
+    }
+}
@@ -0,0 +1,121 @@
+use std::io;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+/// A PHP process listening on a socket, with its output forwarded to the logs.
+pub struct PhpProcess {
+    command: &'static str,
+    args: Vec<String>,
+    child: Mutex<Option<Child>>,
+}
+
+impl PhpProcess {
+    pub fn spawn(command: &'static str, args: &[&str]) -> io::Result<Self> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let child = spawn_child(command, &args)?;
+
+        Ok(Self {
+            command,
+            args,
+            child: Mutex::new(Some(child)),
+        })
+    }
+
+    /// Kills the process and starts a new one, dropping the requests it was
+    /// running.
+    pub async fn restart(&self) -> io::Result<()> {
+        let mut child = self.child.lock().await;
+
+        if let Some(mut child) = child.take() {
+            info!(
+                "Restarting {} process with id {:?}",
+                self.command,
+                child.id()
+            );
+            child.kill().await?;
+        }
+
+        *child = Some(spawn_child(self.command, &self.args)?);
+
+        Ok(())
+    }
+
+    /// Whether the process is still running.
+    pub async fn is_running(&self) -> bool {
+        match self.child.lock().await.as_mut() {
+            Some(child) => matches!(child.try_wait(), Ok(None)),
+            None => false,
+        }
+    }
+
+    pub async fn kill(&self) -> io::Result<()> {
+        if let Some(mut child) = self.child.lock().await.take() {
+            info!(
+                "Shutting down {} process with id {:?}",
+                self.command,
+                child.id()
+            );
+            child.kill().await?;
+        }
+
+        Ok(())
+    }
+}
+
+fn spawn_child(command: &'static str, args: &[String]) -> io::Result<Child> {
+    let mut child = Command::new(command)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    info!("Started {} process with id {:?}", command, child.id());
+
+    // Log process stdout.
+
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+
+            while let Ok(Some(line)) = lines.next_line().await {
+                info!("{}: {}", command, line);
+            }
+        });
+    }
+
+    // Log process stderr.
+
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+
+            while let Ok(Some(line)) = lines.next_line().await {
+                error!("{}: {}", command, line);
+            }
+        });
+    }
+
+    Ok(child)
+}
+
+/// Removes a stale socket and makes sure its parent directory exists.
+pub fn prepare_socket(socket: &str) -> io::Result<()> {
+    let socket_path = Path::new(socket);
+
+    if socket_path.exists() {
+        info!("Socket already exist");
+        std::fs::remove_file(socket_path)?;
+        info!("Existing socket removed");
+    }
+
+    if let Some(parent) = socket_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,334 @@
+use crate::cgi;
+use crate::util::constant_time_eq;
+use lambda_http::http::header::HeaderName;
+use lambda_http::http::HeaderValue;
+use lambda_http::Request;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ProxyError {
+    #[error("Unknown proxy `{0}`, expected one of: api-gateway, cloudfront")]
+    UnknownProxy(String),
+}
+
+/// Headers clients could send to pass for another host or address, that no
+/// proxy in front of the runtime sets.
+const SPOOFABLE_HEADERS: [&str; 2] = ["forwarded", "x-real-ip"];
+
+/// The outermost proxy whose forwarded headers the runtime trusts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrustedProxy {
+    /// The event source: API Gateway, or a function URL or ALB, which set
+    /// `X-Forwarded-Proto`, `X-Forwarded-Port` and `X-Forwarded-For` the
+    /// same way.
+    #[default]
+    ApiGateway,
+    /// CloudFront in front of the event source, proven by a secret it sends
+    /// as a custom origin header. Its requests tell the host, scheme and
+    /// address of viewers.
+    CloudFront,
+}
+
+impl FromStr for TrustedProxy {
+    type Err = ProxyError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "api-gateway" => Ok(Self::ApiGateway),
+            "cloudfront" => Ok(Self::CloudFront),
+            _ => Err(ProxyError::UnknownProxy(value.into())),
+        }
+    }
+}
+
+impl fmt::Display for TrustedProxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ApiGateway => write!(f, "api-gateway"),
+            Self::CloudFront => write!(f, "cloudfront"),
+        }
+    }
+}
+
+/// Address of the client as worked out from trusted proxies, when it isn't
+/// the peer of the event source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientAddr(pub IpAddr);
+
+/// Decides which forwarded headers reach the site, so WordPress builds URLs
+/// and checks addresses from values a client can't forge.
+#[derive(Clone, Debug, Default)]
+pub struct ProxyPolicy {
+    trusted: TrustedProxy,
+    secret: Option<String>,
+}
+
+impl ProxyPolicy {
+    /// The header CloudFront is configured to send `secret` in.
+    pub const SECRET_HEADER: &'static str = "x-sigan-proxy-secret";
+
+    pub fn new(trusted: TrustedProxy, secret: Option<String>) -> Self {
+        Self { trusted, secret }
+    }
+
+    /// Rewrites the forwarded headers of `req` to the ones the policy
+    /// trusts, and removes the others.
+    ///
+    /// Behind CloudFront, `X-Forwarded-Proto` and `X-Forwarded-Port` tell
+    /// what viewers used, `X-Forwarded-Host` is kept and `X-Forwarded-For`
+    /// starts with the viewer address. Otherwise, only the event source's
+    /// scheme and port are kept.
+    pub fn normalize(&self, req: &mut Request) {
+        let from_cloudfront = self.is_from_cloudfront(req);
+        let peer = cgi::remote_addr(req);
+
+        let viewer = match from_cloudfront {
+            true => viewer_addr(req, peer),
+            false => None,
+        };
+
+        let forwarded_proto = match from_cloudfront {
+            true => header(req, "cloudfront-forwarded-proto")
+                .or(header(req, "x-forwarded-proto"))
+                .map(str::to_ascii_lowercase),
+            false => header(req, "x-forwarded-proto").map(str::to_ascii_lowercase),
+        };
+
+        let headers = req.headers_mut();
+
+        headers.remove(Self::SECRET_HEADER);
+
+        for name in SPOOFABLE_HEADERS {
+            headers.remove(name);
+        }
+
+        if !from_cloudfront {
+            let cloudfront_headers: Vec<HeaderName> = headers
+                .keys()
+                .filter(|name| name.as_str().starts_with("cloudfront-"))
+                .cloned()
+                .collect();
+
+            for name in cloudfront_headers {
+                headers.remove(name);
+            }
+
+            headers.remove("x-forwarded-host");
+        }
+
+        // Viewers reach CloudFront on the default port of their scheme.
+        if let Some(proto) = forwarded_proto.filter(|proto| proto == "http" || proto == "https") {
+            if from_cloudfront {
+                let port = if proto == "https" { "443" } else { "80" };
+                headers.insert("x-forwarded-port", HeaderValue::from_static(port));
+            }
+
+            if let Ok(proto) = HeaderValue::from_str(&proto) {
+                headers.insert("x-forwarded-proto", proto);
+            }
+        } else {
+            headers.remove("x-forwarded-proto");
+        }
+
+        // The addresses clients put in `X-Forwarded-For` are dropped.
+        let forwarded_for = [viewer, peer]
+            .into_iter()
+            .flatten()
+            .map(|ip| ip.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        match HeaderValue::from_str(&forwarded_for) {
+            Ok(value) if !forwarded_for.is_empty() => {
+                headers.insert("x-forwarded-for", value);
+            }
+            _ => {
+                headers.remove("x-forwarded-for");
+            }
+        }
+
+        if let Some(viewer) = viewer {
+            req.extensions_mut().insert(ClientAddr(viewer));
+        }
+    }
+
+    fn is_from_cloudfront(&self, req: &Request) -> bool {
+        let Some(secret) = self.secret.as_deref() else {
+            return false;
+        };
+
+        self.trusted == TrustedProxy::CloudFront
+            && header(req, Self::SECRET_HEADER)
+                .is_some_and(|value| constant_time_eq(value.as_bytes(), secret.as_bytes()))
+    }
+}
+
+/// Address of the viewer CloudFront got the request from, in
+/// `CloudFront-Viewer-Address` when the origin request policy forwards it.
+/// Otherwise, CloudFront appended it to `X-Forwarded-For`, before the event
+/// source appended CloudFront's own address.
+fn viewer_addr(req: &Request, peer: Option<IpAddr>) -> Option<IpAddr> {
+    if let Some(address) = header(req, "cloudfront-viewer-address") {
+        return address.rsplit_once(':').and_then(|(ip, _)| ip.parse().ok());
+    }
+
+    let mut addresses = header(req, "x-forwarded-for")?
+        .rsplit(',')
+        .map(str::trim)
+        .peekable();
+
+    if addresses.peek().and_then(|address| address.parse().ok()) == peer {
+        addresses.next();
+    }
+
+    addresses.next()?.parse().ok()
+}
+
+fn header<'a>(req: &'a Request, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cgi::CgiParams;
+    use lambda_http::request::{from_str, RequestContext};
+
+    fn request(headers: &[(&'static str, &str)]) -> Request {
+        let mut req = from_str(include_str!("../tests/fixtures/apigw-v2-get.json")).unwrap();
+
+        for (name, value) in headers {
+            req.headers_mut().insert(*name, value.parse().unwrap());
+        }
+
+        req
+    }
+
+    fn params(policy: &ProxyPolicy, mut req: Request) -> CgiParams {
+        policy.normalize(&mut req);
+        CgiParams::from_request(&req, "/mnt/wordpress", "/index.php")
+    }
+
+    fn cloudfront() -> ProxyPolicy {
+        ProxyPolicy::new(TrustedProxy::CloudFront, Some("s3cr3t".into()))
+    }
+
+    // A viewer at 198.51.100.7, through CloudFront at 130.176.0.1.
+    fn from_cloudfront(secret: &str) -> Request {
+        let mut req = request(&[
+            ("host", "r3pmxmplak.execute-api.us-east-1.amazonaws.com"),
+            ("x-forwarded-host", "blog.example.com"),
+            ("x-forwarded-for", "10.0.0.1, 198.51.100.7, 130.176.0.1"),
+            ("x-forwarded-port", "443"),
+            ("x-forwarded-proto", "https"),
+            ("cloudfront-forwarded-proto", "http"),
+            (ProxyPolicy::SECRET_HEADER, secret),
+        ]);
+
+        if let Some(RequestContext::ApiGatewayV2(context)) =
+            req.extensions_mut().get_mut::<RequestContext>()
+        {
+            context.http.source_ip = Some("130.176.0.1".into());
+        }
+
+        req
+    }
+
+    #[test]
+    fn parses_trusted_proxies() {
+        assert_eq!(
+            "CloudFront".parse::<TrustedProxy>().unwrap(),
+            TrustedProxy::CloudFront
+        );
+        assert_eq!(TrustedProxy::ApiGateway.to_string(), "api-gateway");
+        assert!("alb".parse::<TrustedProxy>().is_err());
+    }
+
+    #[test]
+    fn strips_spoofed_headers() {
+        let params = params(
+            &ProxyPolicy::default(),
+            request(&[
+                ("x-forwarded-host", "evil.example.com"),
+                ("x-forwarded-for", "127.0.0.1, 203.0.113.10"),
+                ("x-real-ip", "127.0.0.1"),
+                ("cloudfront-forwarded-proto", "http"),
+            ]),
+        );
+
+        assert_eq!(params.get("HTTP_HOST"), Some("example.com"));
+        assert_eq!(params.get("SERVER_NAME"), Some("example.com"));
+        assert_eq!(params.get("HTTPS"), Some("on"));
+        assert_eq!(params.get("REMOTE_ADDR"), Some("203.0.113.10"));
+        assert_eq!(params.get("HTTP_X_FORWARDED_FOR"), Some("203.0.113.10"));
+        assert_eq!(params.get("HTTP_X_FORWARDED_HOST"), None);
+        assert_eq!(params.get("HTTP_X_REAL_IP"), None);
+        assert_eq!(params.get("HTTP_CLOUDFRONT_FORWARDED_PROTO"), None);
+    }
+
+    #[test]
+    fn strips_headers_with_underscores() {
+        let params = params(
+            &ProxyPolicy::default(),
+            request(&[
+                ("x_forwarded_host", "evil.example.com"),
+                ("x_real_ip", "127.0.0.1"),
+            ]),
+        );
+
+        assert_eq!(params.get("HTTP_HOST"), Some("example.com"));
+        assert_eq!(params.get("HTTP_X_FORWARDED_HOST"), None);
+        assert_eq!(params.get("HTTP_X_REAL_IP"), None);
+    }
+
+    #[test]
+    fn trusts_cloudfront_with_the_secret() {
+        let params = params(&cloudfront(), from_cloudfront("s3cr3t"));
+
+        assert_eq!(params.get("HTTP_HOST"), Some("blog.example.com"));
+        assert_eq!(params.get("SERVER_NAME"), Some("blog.example.com"));
+        assert_eq!(params.get("HTTPS"), None);
+        assert_eq!(params.get("SERVER_PORT"), Some("80"));
+        assert_eq!(params.get("REMOTE_ADDR"), Some("198.51.100.7"));
+        assert_eq!(
+            params.get("HTTP_X_FORWARDED_FOR"),
+            Some("198.51.100.7, 130.176.0.1")
+        );
+        assert_eq!(params.get("HTTP_X_SIGAN_PROXY_SECRET"), None);
+    }
+
+    #[test]
+    fn prefers_the_viewer_address_header() {
+        let mut req = from_cloudfront("s3cr3t");
+        req.headers_mut().insert(
+            "cloudfront-viewer-address",
+            "2001:db8::7:46532".parse().unwrap(),
+        );
+
+        let params = params(&cloudfront(), req);
+
+        assert_eq!(params.get("REMOTE_ADDR"), Some("2001:db8::7"));
+    }
+
+    #[test]
+    fn distrusts_cloudfront_without_the_secret() {
+        let params = params(&cloudfront(), from_cloudfront("guess"));
+
+        assert_eq!(
+            params.get("HTTP_HOST"),
+            Some("r3pmxmplak.execute-api.us-east-1.amazonaws.com")
+        );
+        assert_eq!(params.get("HTTPS"), Some("on"));
+        assert_eq!(params.get("SERVER_PORT"), Some("443"));
+        assert_eq!(params.get("REMOTE_ADDR"), Some("130.176.0.1"));
+        assert_eq!(params.get("HTTP_X_FORWARDED_FOR"), Some("130.176.0.1"));
+        assert_eq!(params.get("HTTP_X_SIGAN_PROXY_SECRET"), None);
+    }
+}
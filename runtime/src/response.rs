@@ -0,0 +1,326 @@
+use lambda_http::http::header::{
+    HeaderName, HeaderValue, CONTENT_ENCODING, CONTENT_TYPE, LOCATION,
+};
+use lambda_http::http::{self, StatusCode};
+use lambda_http::{Body, Response};
+use thiserror::Error;
+
+// Content types sent as text, everything else is base64 encoded by Lambda.
+const TEXT_CONTENT_TYPES: [&str; 6] = [
+    "text/",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/yaml",
+    "application/x-www-form-urlencoded",
+];
+
+const TEXT_CONTENT_TYPE_SUFFIXES: [&str; 3] = ["+xml", "+json", "+yaml"];
+
+/// Largest body that fits in the 6 MB Lambda response payload once base64
+/// encoded, with some room left for the headers.
+pub const MAX_BODY_SIZE: u64 = 4_500_000;
+
+#[derive(Debug, Error)]
+pub enum ResponseError {
+    #[error("Malformed header in PHP output: `{0}`")]
+    MalformedHeader(String),
+    #[error("Invalid status in PHP output: `{0}`")]
+    InvalidStatus(String),
+    #[error("Failed to build the response: {0}")]
+    Http(#[from] http::Error),
+}
+
+/// Builds a Lambda response from the CGI output of PHP: a `Status:` line, the
+/// headers, an empty line and the body.
+pub fn from_cgi_output(stdout: Vec<u8>) -> Result<Response<Body>, ResponseError> {
+    let (head, body) = split_head(&stdout);
+
+    let builder = parse_head(&head)?;
+    let body = lambda_body(builder.headers_ref(), body);
+
+    Ok(builder.body(body)?)
+}
+
+/// Parses the headers block of the CGI output into a response builder, with
+/// the status set.
+pub fn parse_head(head: &str) -> Result<http::response::Builder, ResponseError> {
+    let mut builder = Response::builder();
+    let mut status = None;
+    let mut location = false;
+
+    for line in head.lines().filter(|line| !line.is_empty()) {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| ResponseError::MalformedHeader(line.into()))?;
+
+        let value = value.trim();
+
+        if name.eq_ignore_ascii_case("status") {
+            status = Some(parse_status(value)?);
+            continue;
+        }
+
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| ResponseError::MalformedHeader(line.into()))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| ResponseError::MalformedHeader(line.into()))?;
+
+        location |= name == LOCATION;
+
+        // Appends, so repeated headers are all kept. `Set-Cookie` headers are
+        // moved to the `cookies` field of API Gateway v2 responses by lambda_http.
+        builder = builder.header(name, value);
+    }
+
+    // A `Location` without an explicit status is a redirect (RFC 3875, 6.2.3).
+    let status = match status {
+        Some(status) => status,
+        None if location => StatusCode::FOUND,
+        None => StatusCode::OK,
+    };
+
+    Ok(builder.status(status))
+}
+
+/// Position and length of the empty line ending the headers, accepting bare
+/// `\n` line endings.
+pub fn head_end(stdout: &[u8]) -> Option<(usize, usize)> {
+    [&b"\r\n\r\n"[..], b"\n\n"]
+        .into_iter()
+        .filter_map(|separator| {
+            stdout
+                .windows(separator.len())
+                .position(|window| window == separator)
+                .map(|position| (position, separator.len()))
+        })
+        .min()
+}
+
+// Splits the output on the first empty line.
+fn split_head(stdout: &[u8]) -> (String, &[u8]) {
+    match head_end(stdout) {
+        Some((position, length)) => (
+            String::from_utf8_lossy(&stdout[..position]).into_owned(),
+            &stdout[position + length..],
+        ),
+        // Without headers, the whole output is the body.
+        None => (String::new(), stdout),
+    }
+}
+
+// Parses `404` and `404 Not Found`.
+fn parse_status(value: &str) -> Result<StatusCode, ResponseError> {
+    value
+        .split_whitespace()
+        .next()
+        .and_then(|code| code.parse::<u16>().ok())
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .ok_or_else(|| ResponseError::InvalidStatus(value.into()))
+}
+
+/// Wraps a body as text or binary depending on its headers. Binary bodies are
+/// base64 encoded in the Lambda response.
+pub fn lambda_body(headers: Option<&http::HeaderMap>, body: &[u8]) -> Body {
+    if body.is_empty() {
+        return Body::Empty;
+    }
+
+    let header = |name| {
+        headers
+            .and_then(|headers| headers.get(name))
+            .and_then(|value| value.to_str().ok())
+    };
+
+    // Compressed bodies are binary whatever their content type.
+    let text =
+        header(CONTENT_ENCODING).is_none() && header(CONTENT_TYPE).is_none_or(is_text_content_type);
+
+    match text {
+        true => match std::str::from_utf8(body) {
+            Ok(body) => Body::Text(body.into()),
+            Err(_) => Body::Binary(body.to_vec()),
+        },
+        false => Body::Binary(body.to_vec()),
+    }
+}
+
+fn is_text_content_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    TEXT_CONTENT_TYPES
+        .iter()
+        .any(|prefix| mime.starts_with(prefix))
+        || TEXT_CONTENT_TYPE_SUFFIXES
+            .iter()
+            .any(|suffix| mime.ends_with(suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lambda_http::request::LambdaRequest;
+    use lambda_http::{service_fn, Adapter, Context, Error, LambdaEvent, Request, Service};
+    use serde_json::{json, Value};
+
+    const OUTPUT: &[u8] = b"Status: 201 Created\r\nContent-Type: text/html\r\n\
+        Set-Cookie: a=1\r\nSet-Cookie: b=2\r\n\r\n<p>Saved</p>";
+
+    // Runs a fixture event through lambda_http, as `run` does, with `output`
+    // from PHP, and returns the response sent back to Lambda.
+    async fn respond(fixture: &str, output: &'static [u8]) -> Value {
+        let payload: LambdaRequest = serde_json::from_str(fixture).unwrap();
+        let event = LambdaEvent::new(payload, Context::default());
+
+        let mut adapter = Adapter::from(service_fn(|_: Request| async {
+            Ok::<_, Error>(from_cgi_output(output.to_vec()).unwrap())
+        }));
+
+        let response = adapter.call(event).await.unwrap();
+
+        serde_json::to_value(response).unwrap()
+    }
+
+    #[test]
+    fn parses_the_status_line() {
+        let response = from_cgi_output(OUTPUT.to_vec()).unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()["content-type"], "text/html");
+        assert_eq!(response.headers().get_all("set-cookie").iter().count(), 2);
+        assert_eq!(response.body(), &Body::Text("<p>Saved</p>".into()));
+
+        let response = from_cgi_output(b"Status: 404\r\n\r\n".to_vec()).unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.body(), &Body::Empty);
+    }
+
+    #[test]
+    fn redirects_with_a_location() {
+        let response = from_cgi_output(b"Location: /wp-admin/\r\n\r\n".to_vec()).unwrap();
+
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.headers()["location"], "/wp-admin/");
+
+        let response =
+            from_cgi_output(b"Status: 301\r\nLocation: /wp-admin/\r\n\r\n".to_vec()).unwrap();
+
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    }
+
+    #[test]
+    fn accepts_bare_line_feeds() {
+        let response =
+            from_cgi_output(b"Status: 403\nContent-Type: text/plain\n\nDenied".to_vec()).unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        assert_eq!(response.body(), &Body::Text("Denied".into()));
+    }
+
+    #[test]
+    fn handles_missing_or_malformed_heads() {
+        // Without an empty line, the whole output is the body.
+        let response = from_cgi_output(b"Hello".to_vec()).unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), &Body::Text("Hello".into()));
+
+        assert!(matches!(
+            from_cgi_output(b"Content-Type text/html\r\n\r\n".to_vec()),
+            Err(ResponseError::MalformedHeader(_))
+        ));
+        assert!(matches!(
+            from_cgi_output(b"Bad Header: x\r\n\r\n".to_vec()),
+            Err(ResponseError::MalformedHeader(_))
+        ));
+        assert!(matches!(
+            from_cgi_output(b"Status: teapot\r\n\r\n".to_vec()),
+            Err(ResponseError::InvalidStatus(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn sends_binary_content_as_base64() {
+        let response = respond(
+            include_str!("../tests/fixtures/apigw-v2-get.json"),
+            b"Content-Type: image/png\r\n\r\n\x89PNG\r\n",
+        )
+        .await;
+
+        assert_eq!(response["isBase64Encoded"], true);
+        assert_eq!(response["body"], "iVBORw0K");
+
+        let headers = |content_type: &str, encoding: Option<&str>| {
+            let mut headers = http::HeaderMap::new();
+            headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+
+            if let Some(encoding) = encoding {
+                headers.insert(CONTENT_ENCODING, encoding.parse().unwrap());
+            }
+
+            headers
+        };
+
+        assert!(matches!(
+            lambda_body(Some(&headers("application/feed+json", None)), b"{}"),
+            Body::Text(_)
+        ));
+        assert!(matches!(
+            lambda_body(Some(&headers("text/html", Some("gzip"))), b"<p>"),
+            Body::Binary(_)
+        ));
+        assert!(matches!(
+            lambda_body(Some(&headers("text/plain", None)), &[0xff, 0xfe]),
+            Body::Binary(_)
+        ));
+        assert!(matches!(lambda_body(None, b""), Body::Empty));
+    }
+
+    #[tokio::test]
+    async fn shapes_http_api_responses() {
+        for fixture in [
+            include_str!("../tests/fixtures/apigw-v2-get.json"),
+            include_str!("../tests/fixtures/function-url-get.json"),
+        ] {
+            let response = respond(fixture, OUTPUT).await;
+
+            assert_eq!(response["statusCode"], 201);
+            assert_eq!(response["body"], "<p>Saved</p>");
+            assert_eq!(response["isBase64Encoded"], false);
+            assert_eq!(response["cookies"], json!(["a=1", "b=2"]));
+            assert_eq!(response["headers"]["content-type"], "text/html");
+            assert_eq!(response["headers"]["set-cookie"], Value::Null);
+        }
+    }
+
+    #[tokio::test]
+    async fn shapes_rest_api_responses() {
+        let response = respond(include_str!("../tests/fixtures/apigw-v1-get.json"), OUTPUT).await;
+
+        assert_eq!(response["statusCode"], 201);
+        assert_eq!(response["body"], "<p>Saved</p>");
+        assert_eq!(response["cookies"], Value::Null);
+        assert_eq!(
+            response["multiValueHeaders"]["set-cookie"],
+            json!(["a=1", "b=2"])
+        );
+    }
+
+    #[tokio::test]
+    async fn shapes_alb_responses() {
+        let response = respond(include_str!("../tests/fixtures/alb-post.json"), OUTPUT).await;
+
+        assert_eq!(response["statusCode"], 201);
+        assert_eq!(response["statusDescription"], "201 Created");
+        assert_eq!(
+            response["multiValueHeaders"]["set-cookie"],
+            json!(["a=1", "b=2"])
+        );
+    }
+}
@@ -0,0 +1,751 @@
+use crate::router::Router;
+use lambda_http::http::{HeaderMap, Method, StatusCode};
+use regex_lite::{Captures, Regex};
+use std::path::Path;
+use thiserror::Error;
+use tracing::warn;
+
+#[derive(Debug, Error)]
+pub enum RewriteError {
+    #[error("Failed to read {0}: {1}")]
+    Read(String, #[source] std::io::Error),
+    #[error("Invalid rewrite directive on line {0}: {1}")]
+    Invalid(usize, String),
+}
+
+/// What the rewrite rules made of a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Rewrite {
+    /// Continues with this URL path and query string, maybe unchanged.
+    Pass {
+        path: String,
+        query: String,
+    },
+    Redirect {
+        status: StatusCode,
+        location: String,
+    },
+    Forbidden,
+}
+
+/// The parts of a request the rewrite rules can look at.
+pub struct RewriteRequest<'a> {
+    pub path: &'a str,
+    pub query: &'a str,
+    pub method: &'a Method,
+    pub headers: &'a HeaderMap,
+    pub https: bool,
+}
+
+#[derive(Debug)]
+enum CondTest {
+    File,
+    Directory,
+    Pattern(Regex),
+}
+
+/// Groups of the last matching condition pattern, for `%N` references.
+type ConditionCaptures = Vec<Option<String>>;
+
+/// A `RewriteCond` directive.
+#[derive(Debug)]
+struct Condition {
+    test_string: String,
+    test: CondTest,
+    negate: bool,
+    or: bool,
+}
+
+/// A `RewriteRule` directive, with the conditions before it.
+#[derive(Debug)]
+struct Rule {
+    pattern: Regex,
+    negate: bool,
+    /// `None` for `-`, which leaves the URL path as is.
+    substitution: Option<String>,
+    conditions: Vec<Condition>,
+    last: bool,
+    redirect: Option<StatusCode>,
+    append_query: bool,
+    forbidden: bool,
+    /// The `RewriteBase` of the file, prefixed to relative substitutions.
+    base: String,
+}
+
+/// A subset of mod_rewrite, enough for the `.htaccess` files of WordPress and
+/// its plugins: `RewriteEngine`, `RewriteBase`, `RewriteCond` and
+/// `RewriteRule` with the `L`, `R`, `QSA`, `F`, `NC` and `OR` flags.
+#[derive(Debug, Default)]
+pub struct RewriteEngine {
+    rules: Vec<Rule>,
+}
+
+impl RewriteEngine {
+    /// Like Apache, rewritten URLs go through the rules again, until they
+    /// stop changing.
+    const MAX_ROUNDS: usize = 10;
+
+    /// Loads the `.htaccess` file in the document root, `None` when there is
+    /// none.
+    pub fn from_document_root(document_root: &Path) -> Result<Option<Self>, RewriteError> {
+        let file = document_root.join(".htaccess");
+
+        match std::fs::read_to_string(&file) {
+            Ok(contents) => Self::parse(&contents).map(Some),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(RewriteError::Read(file.display().to_string(), error)),
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, RewriteError> {
+        let mut engine = Self::default();
+        let mut base = "/".to_string();
+        let mut enabled = false;
+        let mut conditions = Vec::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            let invalid = |message: String| RewriteError::Invalid(index + 1, message);
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let args = split_args(line);
+
+            match args[0].to_ascii_lowercase().as_str() {
+                "rewriteengine" => {
+                    enabled = args
+                        .get(1)
+                        .is_some_and(|value| value.eq_ignore_ascii_case("on"))
+                }
+                "rewritebase" => {
+                    let value = args.get(1).ok_or_else(|| invalid("missing base".into()))?;
+                    base = format!("/{}/", value.trim_matches('/')).replace("//", "/");
+                }
+                "rewritecond" => conditions.push(parse_condition(&args).map_err(invalid)?),
+                "rewriterule" => {
+                    let conditions = std::mem::take(&mut conditions);
+                    engine
+                        .rules
+                        .push(parse_rule(&args, conditions).map_err(invalid)?);
+                }
+                // Other modules, like `<IfModule>` blocks or `Options`.
+                _ => {}
+            }
+        }
+
+        if !enabled {
+            engine.rules.clear();
+        }
+
+        // Like Apache, the base applies to the whole file.
+        for rule in &mut engine.rules {
+            rule.base.clone_from(&base);
+        }
+
+        Ok(engine)
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn rewrite(&self, request: &RewriteRequest, router: &Router) -> Rewrite {
+        let mut path = request.path.to_string();
+        let mut query = request.query.to_string();
+
+        for _ in 0..Self::MAX_ROUNDS {
+            match self.apply(request, router, &path, &query) {
+                Rewrite::Pass {
+                    path: new_path,
+                    query: new_query,
+                } => {
+                    if new_path == path && new_query == query {
+                        break;
+                    }
+
+                    path = new_path;
+                    query = new_query;
+                }
+                rewrite => return rewrite,
+            }
+        }
+
+        Rewrite::Pass { path, query }
+    }
+
+    // Runs the rules once over a URL path.
+    fn apply(&self, request: &RewriteRequest, router: &Router, path: &str, query: &str) -> Rewrite {
+        let mut path = path.to_string();
+        let mut query = query.to_string();
+
+        for rule in &self.rules {
+            // In `.htaccess` files, patterns match the path relative to the
+            // directory, the document root here.
+            let relative_path = path.trim_start_matches('/').to_string();
+
+            let captures = match (rule.pattern.captures(&relative_path), rule.negate) {
+                (Some(captures), false) => Some(captures),
+                (None, true) => None,
+                _ => continue,
+            };
+
+            let variables = Variables {
+                request,
+                router,
+                path: &path,
+                query: &query,
+            };
+
+            let condition_captures =
+                match check_conditions(&rule.conditions, &variables, captures.as_ref()) {
+                    Some(condition_captures) => condition_captures,
+                    None => continue,
+                };
+
+            if rule.forbidden {
+                return Rewrite::Forbidden;
+            }
+
+            if let Some(substitution) = &rule.substitution {
+                let target = expand(
+                    substitution,
+                    captures.as_ref(),
+                    condition_captures.as_ref(),
+                    &variables,
+                );
+
+                let (target_path, target_query) = match target.split_once('?') {
+                    Some((target_path, target_query)) => {
+                        (target_path.to_string(), Some(target_query.to_string()))
+                    }
+                    None => (target, None),
+                };
+
+                let target_query = match target_query {
+                    Some(target_query) if rule.append_query && !query.is_empty() => {
+                        format!("{target_query}&{query}")
+                    }
+                    Some(target_query) => target_query,
+                    None => query.clone(),
+                };
+
+                let external = target_path.contains("://");
+
+                let target_path = match external || target_path.starts_with('/') {
+                    true => target_path,
+                    false => format!("{}{target_path}", rule.base),
+                };
+
+                // Like Apache, a substitution with a host is always a redirect.
+                let redirect = match external {
+                    true => rule.redirect.or(Some(StatusCode::FOUND)),
+                    false => rule.redirect,
+                };
+
+                if let Some(status) = redirect {
+                    let location = match target_query.is_empty() {
+                        true => target_path,
+                        false => format!("{target_path}?{target_query}"),
+                    };
+
+                    return Rewrite::Redirect { status, location };
+                }
+
+                path = target_path;
+                query = target_query;
+            }
+
+            if rule.last {
+                break;
+            }
+        }
+
+        Rewrite::Pass { path, query }
+    }
+}
+
+// Evaluates the conditions of a rule, returning the captures of the last
+// matching pattern for `%N` references. `None` when they don't match.
+fn check_conditions(
+    conditions: &[Condition],
+    variables: &Variables,
+    rule_captures: Option<&Captures>,
+) -> Option<Option<ConditionCaptures>> {
+    let mut last_captures = None;
+    let mut matched = true;
+    let mut any_of: Option<bool> = None;
+
+    for condition in conditions {
+        let value = expand(
+            &condition.test_string,
+            rule_captures,
+            last_captures.as_ref(),
+            variables,
+        );
+
+        let result = match &condition.test {
+            CondTest::File => variables.router.is_file(Path::new(&value)),
+            CondTest::Directory => variables.router.is_dir(Path::new(&value)),
+            CondTest::Pattern(pattern) => match pattern.captures(&value) {
+                Some(captures) => {
+                    if !condition.negate {
+                        last_captures = Some(
+                            captures
+                                .iter()
+                                .map(|capture| capture.map(|capture| capture.as_str().to_string()))
+                                .collect(),
+                        );
+                    }
+                    true
+                }
+                None => false,
+            },
+        };
+
+        let result = result != condition.negate;
+
+        // `[OR]` joins a condition with the next one.
+        let result = any_of.take().unwrap_or(false) || result;
+
+        match condition.or {
+            true => any_of = Some(result),
+            false => matched &= result,
+        }
+    }
+
+    if let Some(result) = any_of {
+        matched &= result;
+    }
+
+    matched.then_some(last_captures)
+}
+
+/// Server variables, as referenced by `%{NAME}`.
+struct Variables<'a> {
+    request: &'a RewriteRequest<'a>,
+    router: &'a Router,
+    path: &'a str,
+    query: &'a str,
+}
+
+impl Variables<'_> {
+    fn get(&self, name: &str) -> String {
+        let header = |name: &str| {
+            self.request
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string()
+        };
+
+        match name {
+            // Like Apache, the script for paths with a `PATH_INFO`, so
+            // `-f` conditions hold for `/index.php/wp-json/`.
+            "REQUEST_FILENAME" | "SCRIPT_FILENAME" => {
+                let path = match self.router.split_path_info(self.path) {
+                    Some((script_name, _)) => script_name,
+                    None => self.path,
+                };
+
+                self.router.local_path(path).to_string_lossy().into_owned()
+            }
+            "REQUEST_URI" => self.path.into(),
+            "QUERY_STRING" => self.query.into(),
+            "REQUEST_METHOD" => self.request.method.to_string(),
+            "HTTPS" => if self.request.https { "on" } else { "off" }.into(),
+            "DOCUMENT_ROOT" => self.router.document_root().to_string_lossy().into_owned(),
+            name => match name.strip_prefix("HTTP:") {
+                Some(header_name) => header(header_name),
+                None => match name.strip_prefix("HTTP_") {
+                    Some(header_name) => header(&header_name.replace('_', "-")),
+                    None => String::new(),
+                },
+            },
+        }
+    }
+}
+
+// Replaces `$N` rule references, `%N` condition references and `%{NAME}`
+// variables.
+fn expand(
+    template: &str,
+    rule_captures: Option<&Captures>,
+    condition_captures: Option<&ConditionCaptures>,
+    variables: &Variables,
+) -> String {
+    let mut result = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        match (char, chars.peek().copied()) {
+            ('$', Some(digit)) if digit.is_ascii_digit() => {
+                chars.next();
+                let index = digit.to_digit(10).unwrap_or_default() as usize;
+
+                if let Some(capture) = rule_captures.and_then(|captures| captures.get(index)) {
+                    result.push_str(capture.as_str());
+                }
+            }
+            ('%', Some(digit)) if digit.is_ascii_digit() => {
+                chars.next();
+                let index = digit.to_digit(10).unwrap_or_default() as usize;
+
+                if let Some(Some(capture)) =
+                    condition_captures.and_then(|captures| captures.get(index))
+                {
+                    result.push_str(capture);
+                }
+            }
+            ('%', Some('{')) => {
+                chars.next();
+                let name: String = chars.by_ref().take_while(|char| *char != '}').collect();
+                result.push_str(&variables.get(&name));
+            }
+            (char, _) => result.push(char),
+        }
+    }
+
+    result
+}
+
+fn parse_condition(args: &[String]) -> Result<Condition, String> {
+    let (test_string, pattern) = match args {
+        [_, test_string, pattern, ..] => (test_string, pattern),
+        _ => return Err("RewriteCond needs a test string and a pattern".into()),
+    };
+
+    let flags = parse_flags(args.get(3))?;
+    let mut case_insensitive = false;
+    let mut or = false;
+
+    for (name, _) in &flags {
+        match name.as_str() {
+            "NC" | "NOCASE" => case_insensitive = true,
+            "OR" | "ORNEXT" => or = true,
+            name => warn!("Ignoring unsupported RewriteCond flag `{}`", name),
+        }
+    }
+
+    let (negate, pattern) = match pattern.strip_prefix('!') {
+        Some(pattern) => (true, pattern),
+        None => (false, pattern.as_str()),
+    };
+
+    let test = match pattern {
+        "-f" | "-F" => CondTest::File,
+        "-d" => CondTest::Directory,
+        pattern if pattern.starts_with('-') => {
+            return Err(format!("unsupported condition `{pattern}`"))
+        }
+        pattern => CondTest::Pattern(compile(pattern, case_insensitive)?),
+    };
+
+    Ok(Condition {
+        test_string: test_string.clone(),
+        test,
+        negate,
+        or,
+    })
+}
+
+fn parse_rule(args: &[String], conditions: Vec<Condition>) -> Result<Rule, String> {
+    let (pattern, substitution) = match args {
+        [_, pattern, substitution, ..] => (pattern, substitution),
+        _ => return Err("RewriteRule needs a pattern and a substitution".into()),
+    };
+
+    let mut rule = Rule {
+        pattern: Regex::new("").map_err(|error| error.to_string())?,
+        negate: false,
+        substitution: (substitution != "-").then(|| substitution.clone()),
+        conditions,
+        last: false,
+        redirect: None,
+        append_query: false,
+        forbidden: false,
+        base: "/".into(),
+    };
+
+    let mut case_insensitive = false;
+
+    for (name, value) in parse_flags(args.get(3))? {
+        match name.as_str() {
+            "L" | "LAST" => rule.last = true,
+            "QSA" | "QSAPPEND" => rule.append_query = true,
+            "NC" | "NOCASE" => case_insensitive = true,
+            "F" | "FORBIDDEN" => {
+                rule.forbidden = true;
+                rule.last = true;
+            }
+            "R" | "REDIRECT" => {
+                let status = match value {
+                    Some(value) => value
+                        .parse()
+                        .ok()
+                        .and_then(|status| StatusCode::from_u16(status).ok())
+                        .filter(StatusCode::is_redirection)
+                        .ok_or_else(|| format!("invalid redirect status `{value}`"))?,
+                    None => StatusCode::FOUND,
+                };
+
+                rule.redirect = Some(status);
+                rule.last = true;
+            }
+            name => warn!("Ignoring unsupported RewriteRule flag `{}`", name),
+        }
+    }
+
+    let (negate, pattern) = match pattern.strip_prefix('!') {
+        Some(pattern) => (true, pattern),
+        None => (false, pattern.as_str()),
+    };
+
+    rule.pattern = compile(pattern, case_insensitive)?;
+    rule.negate = negate;
+
+    Ok(rule)
+}
+
+// Parses `[R=301,L]` into upper case names and values.
+fn parse_flags(flags: Option<&String>) -> Result<Vec<(String, Option<String>)>, String> {
+    let flags = match flags {
+        Some(flags) => flags
+            .strip_prefix('[')
+            .and_then(|flags| flags.strip_suffix(']'))
+            .ok_or_else(|| format!("invalid flags `{flags}`"))?,
+        None => return Ok(Vec::new()),
+    };
+
+    let flags = flags
+        .split(',')
+        .map(str::trim)
+        .filter(|flag| !flag.is_empty())
+        .map(|flag| match flag.split_once('=') {
+            Some((name, value)) => (name.to_ascii_uppercase(), Some(value.to_string())),
+            None => (flag.to_ascii_uppercase(), None),
+        })
+        .collect();
+
+    Ok(flags)
+}
+
+fn compile(pattern: &str, case_insensitive: bool) -> Result<Regex, String> {
+    let pattern = match case_insensitive {
+        true => format!("(?i){pattern}"),
+        false => pattern.to_string(),
+    };
+
+    Regex::new(&pattern).map_err(|error| format!("invalid pattern `{pattern}`: {error}"))
+}
+
+// Splits a directive on whitespace, keeping quoted arguments together.
+fn split_args(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for char in line.chars() {
+        match char {
+            '"' => quoted = !quoted,
+            char if char.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+            }
+            char => current.push(char),
+        }
+    }
+
+    if !current.is_empty() {
+        args.push(current);
+    }
+
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::DocumentRoot;
+    use std::fs;
+
+    const WORDPRESS: &str = r#"
+# BEGIN WordPress
+<IfModule mod_rewrite.c>
+RewriteEngine On
+RewriteRule .* - [E=HTTP_AUTHORIZATION:%{HTTP:Authorization}]
+RewriteBase /
+RewriteRule ^index\.php$ - [L]
+RewriteCond %{REQUEST_FILENAME} !-f
+RewriteCond %{REQUEST_FILENAME} !-d
+RewriteRule . /index.php [L]
+</IfModule>
+# END WordPress
+"#;
+
+    fn wordpress(name: &str) -> DocumentRoot {
+        DocumentRoot::new(&format!("rewrite-{name}"))
+            .dir("wp-admin")
+            .file("index.php", "<?php")
+            .file("wp-login.php", "<?php")
+    }
+
+    fn rewrite(
+        engine: &RewriteEngine,
+        root: &DocumentRoot,
+        uri: &str,
+        headers: &[(&'static str, &str)],
+    ) -> Rewrite {
+        let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+        let mut header_map = HeaderMap::new();
+
+        for (name, value) in headers {
+            header_map.insert(*name, value.parse().unwrap());
+        }
+
+        let request = RewriteRequest {
+            path,
+            query,
+            method: &Method::GET,
+            headers: &header_map,
+            https: true,
+        };
+
+        engine.rewrite(&request, &Router::new(root.path()))
+    }
+
+    fn pass(path: &str, query: &str) -> Rewrite {
+        Rewrite::Pass {
+            path: path.into(),
+            query: query.into(),
+        }
+    }
+
+    #[test]
+    fn applies_wordpress_rules() {
+        let root = wordpress("wordpress");
+        let engine = RewriteEngine::parse(WORDPRESS).unwrap();
+
+        assert_eq!(engine.len(), 3);
+        assert_eq!(
+            rewrite(&engine, &root, "/wp-login.php", &[]),
+            pass("/wp-login.php", "")
+        );
+        assert_eq!(
+            rewrite(&engine, &root, "/wp-admin", &[]),
+            pass("/wp-admin", "")
+        );
+        assert_eq!(
+            rewrite(&engine, &root, "/hello-world/?p=1", &[]),
+            pass("/index.php", "p=1")
+        );
+
+        // The script exists, like for Apache with a `PATH_INFO`.
+        assert_eq!(
+            rewrite(&engine, &root, "/wp-login.php/extra", &[]),
+            pass("/wp-login.php/extra", "")
+        );
+    }
+
+    #[test]
+    fn ignores_disabled_engines() {
+        let engine = RewriteEngine::parse("RewriteRule . /index.php [L]").unwrap();
+
+        assert_eq!(engine.len(), 0);
+    }
+
+    #[test]
+    fn redirects() {
+        let root = wordpress("redirects");
+        let engine = RewriteEngine::parse(
+            r#"
+            RewriteEngine On
+            RewriteBase /
+            RewriteCond %{HTTP_HOST} ^www\.(.+)$ [NC]
+            RewriteRule ^(.*)$ https://%1/$1 [R=301,L]
+            RewriteRule ^([_0-9a-zA-Z-]+/)?wp-admin$ $1wp-admin/ [R=301,L]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            rewrite(&engine, &root, "/blog/?p=1", &[("host", "WWW.example.com")]),
+            Rewrite::Redirect {
+                status: StatusCode::MOVED_PERMANENTLY,
+                location: "https://example.com/blog/?p=1".into(),
+            }
+        );
+        assert_eq!(
+            rewrite(&engine, &root, "/site/wp-admin", &[("host", "example.com")]),
+            Rewrite::Redirect {
+                status: StatusCode::MOVED_PERMANENTLY,
+                location: "/site/wp-admin/".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn appends_query_strings() {
+        let root = wordpress("query-strings");
+        let engine = RewriteEngine::parse(
+            r#"
+            RewriteEngine On
+            RewriteRule ^feed/(\w+)$ /index.php?feed=$1 [QSA,L]
+            RewriteRule ^old/(\w+)$ /index.php?page=$1 [L]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            rewrite(&engine, &root, "/feed/rss?paged=2", &[]),
+            pass("/index.php", "feed=rss&paged=2")
+        );
+        assert_eq!(
+            rewrite(&engine, &root, "/old/about?paged=2", &[]),
+            pass("/index.php", "page=about")
+        );
+    }
+
+    #[test]
+    fn forbids() {
+        let root = wordpress("forbids");
+        let engine = RewriteEngine::parse(
+            r#"
+            RewriteEngine On
+            RewriteCond %{REQUEST_FILENAME} -f [OR]
+            RewriteCond %{REQUEST_URI} ^/private/
+            RewriteRule \.(txt|log)$ - [F]
+            "#,
+        )
+        .unwrap();
+
+        fs::write(root.join("debug.log"), "").unwrap();
+
+        assert_eq!(
+            rewrite(&engine, &root, "/debug.log", &[]),
+            Rewrite::Forbidden
+        );
+        assert_eq!(
+            rewrite(&engine, &root, "/private/notes.txt", &[]),
+            Rewrite::Forbidden
+        );
+        assert_eq!(
+            rewrite(&engine, &root, "/missing.log", &[]),
+            pass("/missing.log", "")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_directives() {
+        assert!(RewriteEngine::parse("RewriteEngine On\nRewriteRule (").is_err());
+        assert!(RewriteEngine::parse("RewriteEngine On\nRewriteRule ( /x").is_err());
+        assert!(RewriteEngine::parse(
+            "RewriteEngine On\nRewriteCond %{HTTP_HOST} -x\nRewriteRule . /"
+        )
+        .is_err());
+        assert!(RewriteEngine::parse("RewriteEngine On\nRewriteRule . / [R=200]").is_err());
+    }
+}
@@ -0,0 +1,432 @@
+use crate::context::RuntimeContext;
+use lambda_http::Request;
+use percent_encoding::percent_decode_str;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// Where a request is sent, relative to the document root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Route {
+    /// Runs a PHP script, given by its `SCRIPT_NAME`.
+    Script(String),
+    /// Runs a PHP script with the rest of the path as `PATH_INFO`, like
+    /// `/index.php/wp-json/wp/v2/posts`.
+    PathInfo {
+        script_name: String,
+        path_info: String,
+    },
+    /// Serves a file as is, it may not exist.
+    Static(PathBuf),
+    Forbidden,
+}
+
+/// Maps URL paths to files, the way WordPress expects from a web server.
+pub struct Router {
+    document_root: PathBuf,
+    stat_cache: StatCache,
+}
+
+impl Router {
+    pub fn new(document_root: impl Into<PathBuf>) -> Self {
+        Self {
+            document_root: document_root.into(),
+            stat_cache: StatCache::default(),
+        }
+    }
+
+    pub fn document_root(&self) -> &Path {
+        &self.document_root
+    }
+
+    /// Context of a request routed to `script_name`, with the `path_info`
+    /// split from its path.
+    pub fn context(
+        &self,
+        req: &Request,
+        script_name: &str,
+        path_info: Option<&str>,
+    ) -> RuntimeContext {
+        let context = RuntimeContext::new(req, &self.document_root, script_name);
+
+        match path_info {
+            Some(path_info) => context.with_path_info(path_info),
+            None => context,
+        }
+    }
+
+    /// Routes a URL path, without its query string.
+    pub fn route(&self, path: &str) -> Route {
+        // Never leave the document root.
+        if path.split('/').any(|segment| segment == "..") {
+            return Route::Forbidden;
+        }
+
+        if let Some((script_name, path_info)) = self.split_path_info(path) {
+            return Route::PathInfo {
+                script_name: script_name.into(),
+                path_info: path_info.into(),
+            };
+        }
+
+        let path = path.trim_end_matches('/');
+        let local_path = self.local_path(path);
+
+        match self.stat_cache.kind(&local_path) {
+            FileKind::Directory => Route::Script(format!("{path}/index.php")),
+            FileKind::File => self.file_route(path, local_path),
+            // Covers paths to files that don't exist yet, like new uploads.
+            FileKind::Missing if has_extension(path) => self.file_route(path, local_path),
+            FileKind::Missing => Route::Script("/index.php".into()),
+        }
+    }
+
+    /// Splits a path at the first `.php` segment followed by more, like
+    /// `fastcgi_split_path_info ^(.+?\.php)(/.*)$` in nginx. Only paths to
+    /// existing scripts are split.
+    pub fn split_path_info<'a>(&self, path: &'a str) -> Option<(&'a str, &'a str)> {
+        let (index, _) = path.match_indices(".php/").next()?;
+        let (script_name, path_info) = path.split_at(index + ".php".len());
+
+        self.is_file(&self.local_path(script_name))
+            .then_some((script_name, path_info))
+    }
+
+    /// Whether a local path is a file, cached like routes.
+    pub fn is_file(&self, local_path: &Path) -> bool {
+        self.stat_cache.kind(local_path) == FileKind::File
+    }
+
+    /// Whether a local path is a directory, cached like routes.
+    pub fn is_dir(&self, local_path: &Path) -> bool {
+        self.stat_cache.kind(local_path) == FileKind::Directory
+    }
+
+    /// Path of a URL path in the document root.
+    pub fn local_path(&self, path: &str) -> PathBuf {
+        self.document_root.join(path.trim_start_matches('/'))
+    }
+
+    fn file_route(&self, path: &str, local_path: PathBuf) -> Route {
+        match path.ends_with(".php") {
+            true => Route::Script(path.into()),
+            false => Route::Static(local_path),
+        }
+    }
+}
+
+/// Canonical form of a URL path, the one the rules check and the router
+/// routes: segments are percent-decoded, repeated `/` collapsed and `.`
+/// segments dropped, so `//wp-content/./x%2Ephp` can't pass for another path
+/// than `/wp-content/x.php`. `None` for paths with `..`, or with a `/` or a
+/// NUL byte once decoded.
+pub fn normalize_path(path: &str) -> Option<String> {
+    let mut normalized = String::with_capacity(path.len());
+
+    for segment in path.split('/') {
+        let segment = percent_decode_str(segment).decode_utf8().ok()?;
+
+        match segment.as_ref() {
+            "" | "." => continue,
+            ".." => return None,
+            segment if segment.contains(['/', '\0']) => return None,
+            segment => {
+                normalized.push('/');
+                normalized.push_str(segment);
+            }
+        }
+    }
+
+    // Rewrites tell directories apart by their trailing `/`.
+    if normalized.is_empty() || path.ends_with('/') || path.ends_with("/.") {
+        normalized.push('/');
+    }
+
+    Some(normalized)
+}
+
+fn has_extension(path: &str) -> bool {
+    path.rsplit('/')
+        .next()
+        .is_some_and(|basename| basename.contains('.'))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FileKind {
+    File,
+    Directory,
+    Missing,
+}
+
+/// Remembers file metadata for a little while, stat calls on EFS are slow.
+struct StatCache {
+    entries: RwLock<HashMap<PathBuf, (FileKind, Instant)>>,
+    ttl: Duration,
+}
+
+impl StatCache {
+    const TTL: Duration = Duration::from_secs(10);
+
+    // Every URL path gets an entry, so the cache is flushed once it gets big.
+    const MAX_ENTRIES: usize = 10_000;
+
+    fn kind(&self, path: &Path) -> FileKind {
+        let now = Instant::now();
+
+        let entries = self
+            .entries
+            .read()
+            .unwrap_or_else(|error| error.into_inner());
+
+        if let Some((kind, checked_at)) = entries.get(path) {
+            if now.duration_since(*checked_at) < self.ttl {
+                return *kind;
+            }
+        }
+
+        drop(entries);
+
+        let kind = match std::fs::metadata(path) {
+            Ok(metadata) if metadata.is_dir() => FileKind::Directory,
+            Ok(_) => FileKind::File,
+            Err(_) => FileKind::Missing,
+        };
+
+        let mut entries = self
+            .entries
+            .write()
+            .unwrap_or_else(|error| error.into_inner());
+
+        if entries.len() >= Self::MAX_ENTRIES {
+            entries.clear();
+        }
+
+        entries.insert(path.to_path_buf(), (kind, now));
+
+        kind
+    }
+}
+
+impl Default for StatCache {
+    fn default() -> Self {
+        Self {
+            entries: RwLock::default(),
+            ttl: Self::TTL,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::DocumentRoot;
+    use std::fs;
+
+    // A small WordPress tree.
+    fn wordpress(name: &str) -> DocumentRoot {
+        DocumentRoot::new(&format!("router-{name}"))
+            .file("index.php", "<?php")
+            .file("wp-login.php", "<?php")
+            .file("wp-admin/index.php", "<?php")
+            .file("wp-content/uploads/logo.png", "")
+    }
+
+    #[test]
+    fn forbids_leaving_the_document_root() {
+        let root = wordpress("traversal");
+        let router = Router::new(root.path());
+
+        assert_eq!(router.route("/wp-admin/../../etc/passwd"), Route::Forbidden);
+    }
+
+    #[test]
+    fn routes_directories_to_their_index() {
+        let root = wordpress("directories");
+        let router = Router::new(root.path());
+
+        assert_eq!(
+            router.route("/wp-admin/"),
+            Route::Script("/wp-admin/index.php".into())
+        );
+        assert_eq!(
+            router.route("/wp-admin"),
+            Route::Script("/wp-admin/index.php".into())
+        );
+        assert_eq!(router.route("/"), Route::Script("/index.php".into()));
+    }
+
+    #[test]
+    fn routes_existing_files() {
+        let root = wordpress("existing-files");
+        let router = Router::new(root.path());
+
+        assert_eq!(
+            router.route("/wp-login.php"),
+            Route::Script("/wp-login.php".into())
+        );
+        assert_eq!(
+            router.route("/wp-content/uploads/logo.png"),
+            Route::Static(root.join("wp-content/uploads/logo.png"))
+        );
+    }
+
+    #[test]
+    fn routes_missing_files_with_an_extension() {
+        let root = wordpress("missing-files");
+        let router = Router::new(root.path());
+
+        assert_eq!(
+            router.route("/wp-missing.php"),
+            Route::Script("/wp-missing.php".into())
+        );
+        assert_eq!(
+            router.route("/wp-content/uploads/new.jpg"),
+            Route::Static(root.join("wp-content/uploads/new.jpg"))
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_root_index() {
+        let root = wordpress("fallback");
+        let router = Router::new(root.path());
+
+        assert_eq!(
+            router.route("/blog/hello-world/"),
+            Route::Script("/index.php".into())
+        );
+    }
+
+    #[test]
+    fn splits_path_info() {
+        let root = wordpress("path-info");
+        let router = Router::new(root.path());
+
+        let path_info = |script_name: &str, path_info: &str| Route::PathInfo {
+            script_name: script_name.into(),
+            path_info: path_info.into(),
+        };
+
+        assert_eq!(
+            router.route("/index.php/wp-json/wp/v2/posts"),
+            path_info("/index.php", "/wp-json/wp/v2/posts")
+        );
+        assert_eq!(router.route("/index.php/"), path_info("/index.php", "/"));
+        assert_eq!(
+            router.route("/wp-admin/index.php/extra/file.json"),
+            path_info("/wp-admin/index.php", "/extra/file.json")
+        );
+
+        // The first script wins, like with a lazy match in nginx.
+        assert_eq!(
+            router.route("/index.php/wp-login.php/x"),
+            path_info("/index.php", "/wp-login.php/x")
+        );
+    }
+
+    #[test]
+    fn only_splits_existing_scripts() {
+        let root = wordpress("path-info-missing");
+        let router = Router::new(root.path());
+
+        assert_eq!(
+            router.route("/index.php"),
+            Route::Script("/index.php".into())
+        );
+        assert_eq!(
+            router.route("/missing.php/wp-json/"),
+            Route::Script("/index.php".into())
+        );
+        assert_eq!(
+            router.route("/index.php.bak/x"),
+            Route::Script("/index.php".into())
+        );
+        assert_eq!(
+            router.route("/wp-admin/index.phpx/y"),
+            Route::Script("/index.php".into())
+        );
+        assert_eq!(
+            router.route("/index.php/../wp-config.php"),
+            Route::Forbidden
+        );
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(normalize_path("/").as_deref(), Some("/"));
+        assert_eq!(normalize_path("").as_deref(), Some("/"));
+        assert_eq!(normalize_path("/wp-admin/").as_deref(), Some("/wp-admin/"));
+        assert_eq!(
+            normalize_path("//wp-content//plugins/x/evil.php").as_deref(),
+            Some("/wp-content/plugins/x/evil.php")
+        );
+        assert_eq!(
+            normalize_path("/./wp-content/./plugins/x/evil.php").as_deref(),
+            Some("/wp-content/plugins/x/evil.php")
+        );
+        assert_eq!(normalize_path("/wp-admin/.").as_deref(), Some("/wp-admin/"));
+        assert_eq!(normalize_path("/wp-admin/../wp-config.php"), None);
+        assert_eq!(normalize_path("/.."), None);
+    }
+
+    #[test]
+    fn decodes_paths() {
+        let root = wordpress("decoding");
+        let router = Router::new(root.path());
+
+        fs::write(root.join("wp-content/uploads/caf\u{e9}.jpg"), "").unwrap();
+        fs::write(root.join("my file.pdf"), "").unwrap();
+
+        let route = |path| router.route(&normalize_path(path).unwrap());
+
+        assert_eq!(
+            route("/wp-content/uploads/caf%C3%A9.jpg"),
+            Route::Static(root.join("wp-content/uploads/caf\u{e9}.jpg"))
+        );
+        assert_eq!(
+            route("/my%20file.pdf"),
+            Route::Static(root.join("my file.pdf"))
+        );
+        assert_eq!(
+            route("/wp-login%2Ephp"),
+            Route::Script("/wp-login.php".into())
+        );
+    }
+
+    #[test]
+    fn rejects_encoded_separators_and_traversal() {
+        assert_eq!(normalize_path("/wp-admin%2F..%2Fwp-config.php"), None);
+        assert_eq!(normalize_path("/wp-admin/%2e%2e/wp-config.php"), None);
+        assert_eq!(normalize_path("/index.php%00.jpg"), None);
+        assert_eq!(normalize_path("/caf%E9.jpg"), None);
+        assert_eq!(
+            normalize_path("/%2e/wp-login.php").as_deref(),
+            Some("/wp-login.php")
+        );
+    }
+
+    #[test]
+    fn caches_file_metadata() {
+        let root = wordpress("stat-cache");
+        let router = Router::new(root.path());
+
+        assert_eq!(router.route("/blog"), Route::Script("/index.php".into()));
+
+        // Still missing for the router until the entry expires.
+        fs::create_dir(root.join("blog")).unwrap();
+        assert_eq!(router.route("/blog"), Route::Script("/index.php".into()));
+
+        let router = Router {
+            stat_cache: StatCache {
+                ttl: Duration::ZERO,
+                ..StatCache::default()
+            },
+            ..Router::new(root.path())
+        };
+
+        assert_eq!(
+            router.route("/blog"),
+            Route::Script("/blog/index.php".into())
+        );
+    }
+}
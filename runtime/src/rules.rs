@@ -0,0 +1,487 @@
+use crate::cgi::remote_addr;
+use lambda_http::http::{Method, StatusCode};
+use lambda_http::Request;
+use regex_lite::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use thiserror::Error;
+
+/// Used when no rules file exists: PHP files in `wp-content` and
+/// `wp-includes` are only meant to be included, and configuration, source,
+/// hidden or backup files must never be served. `/.well-known/` stays public.
+const DEFAULT_RULES: &str = r#"
+[[rules]]
+action = "deny"
+regex = '^/(?:wp-content|wp-includes)/.*\.php$'
+
+[[rules]]
+action = "allow"
+path = "/.well-known/**"
+
+[[rules]]
+action = "deny"
+regex = '/\.'
+
+[[rules]]
+action = "deny"
+regex = '(?:~|\.(?:bak|old|orig|save|swp|tmp))$'
+
+[[rules]]
+action = "deny"
+regex = '\.(?:crt|ini|htaccess|json|scss)$'
+"#;
+
+#[derive(Debug, Error)]
+pub enum RulesError {
+    #[error("Failed to read the rules file {0}: {1}")]
+    Read(String, #[source] std::io::Error),
+    #[error("Failed to parse the rules: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Invalid access rule #{0}: {1}")]
+    Invalid(usize, String),
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+/// A rule as written in the rules file. Every condition given must match.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleDefinition {
+    action: Action,
+    /// Glob on the URL path: `*` stops at `/`, `**` doesn't.
+    path: Option<String>,
+    /// Regex on the URL path.
+    regex: Option<String>,
+    #[serde(default)]
+    methods: Vec<String>,
+    /// Globs on header values, by header name.
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    /// Client addresses or CIDR ranges.
+    #[serde(default)]
+    ips: Vec<String>,
+    /// Status of denied requests, 403 by default.
+    status: Option<u16>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    rules: Vec<RuleDefinition>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    action: Action,
+    path: Option<Regex>,
+    methods: Vec<Method>,
+    headers: Vec<(String, Regex)>,
+    ips: Vec<IpRange>,
+    status: StatusCode,
+}
+
+impl Rule {
+    fn matches(&self, req: &Request, path: &str, client_ip: Option<IpAddr>) -> bool {
+        if let Some(pattern) = &self.path {
+            if !pattern.is_match(path) {
+                return false;
+            }
+        }
+
+        if !self.methods.is_empty() && !self.methods.contains(req.method()) {
+            return false;
+        }
+
+        let headers_match = self.headers.iter().all(|(name, pattern)| {
+            req.headers()
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .any(|value| pattern.is_match(value))
+        });
+
+        if !headers_match {
+            return false;
+        }
+
+        self.ips.is_empty()
+            || client_ip.is_some_and(|ip| self.ips.iter().any(|range| range.contains(ip)))
+    }
+}
+
+impl TryFrom<RuleDefinition> for Rule {
+    type Error = String;
+
+    fn try_from(definition: RuleDefinition) -> Result<Self, Self::Error> {
+        let path = match (definition.path, definition.regex) {
+            (Some(_), Some(_)) => return Err("use either `path` or `regex`, not both".into()),
+            (Some(glob), None) => Some(glob_to_regex(&glob, false)?),
+            (None, Some(regex)) => {
+                Some(Regex::new(&regex).map_err(|error| format!("invalid regex: {error}"))?)
+            }
+            (None, None) => None,
+        };
+
+        let methods = definition
+            .methods
+            .iter()
+            .map(|method| {
+                Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                    .map_err(|_| format!("invalid method `{method}`"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let headers = definition
+            .headers
+            .iter()
+            .map(|(name, glob)| Ok((name.to_ascii_lowercase(), glob_to_regex(glob, true)?)))
+            .collect::<Result<_, String>>()?;
+
+        let ips = definition
+            .ips
+            .iter()
+            .map(|ip| ip.parse())
+            .collect::<Result<_, _>>()?;
+
+        let status = match (definition.action, definition.status) {
+            (Action::Allow, Some(_)) => return Err("`status` only applies to `deny`".into()),
+            (_, Some(status)) if (100..600).contains(&status) => {
+                StatusCode::from_u16(status).map_err(|error| error.to_string())?
+            }
+            (_, Some(status)) => return Err(format!("invalid status `{status}`")),
+            (_, None) => StatusCode::FORBIDDEN,
+        };
+
+        Ok(Self {
+            action: definition.action,
+            path,
+            methods,
+            headers,
+            ips,
+            status,
+        })
+    }
+}
+
+/// Ordered allow and deny rules, checked before requests are routed. The
+/// first matching rule wins, requests matching none are allowed.
+#[derive(Debug)]
+pub struct Rules(Vec<Rule>);
+
+impl Rules {
+    /// Loads the rules file, the default rules are used when it doesn't
+    /// exist.
+    pub fn from_file(file: &Path) -> Result<Self, RulesError> {
+        match file.exists() {
+            true => Self::load(file),
+            false => Self::parse(DEFAULT_RULES),
+        }
+    }
+
+    pub fn load(file: &Path) -> Result<Self, RulesError> {
+        let contents = std::fs::read_to_string(file)
+            .map_err(|error| RulesError::Read(file.display().to_string(), error))?;
+
+        Self::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, RulesError> {
+        let file: RulesFile = toml::from_str(contents)?;
+
+        let rules = file
+            .rules
+            .into_iter()
+            .enumerate()
+            .map(|(index, definition)| {
+                Rule::try_from(definition).map_err(|error| RulesError::Invalid(index + 1, error))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self(rules))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Status to respond with when the request is denied.
+    pub fn check(&self, req: &Request, path: &str) -> Option<StatusCode> {
+        let client_ip = remote_addr(req);
+
+        let rule = self
+            .0
+            .iter()
+            .find(|rule| rule.matches(req, path, client_ip))?;
+
+        match rule.action {
+            Action::Allow => None,
+            Action::Deny => Some(rule.status),
+        }
+    }
+}
+
+/// A single address, or a CIDR range like `10.0.0.0/8`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct IpRange {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(range) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(range) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::str::FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid IP address or range `{value}`");
+
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+
+        let address: IpAddr = address.trim().parse().map_err(|_| invalid())?;
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().map_err(|_| invalid())?,
+            None => max_prefix,
+        };
+
+        if prefix > max_prefix {
+            return Err(invalid());
+        }
+
+        Ok(Self { address, prefix })
+    }
+}
+
+// Translates a glob to an anchored regex.
+fn glob_to_regex(glob: &str, case_insensitive: bool) -> Result<Regex, String> {
+    let mut regex = String::from(if case_insensitive { "(?i)^" } else { "^" });
+    let mut chars = glob.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            // Header values have no segments.
+            '*' if case_insensitive => regex.push_str(".*"),
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push('.'),
+            char => regex.push_str(&regex_lite::escape(&char.to_string())),
+        }
+    }
+
+    regex.push('$');
+
+    Regex::new(&regex).map_err(|error| format!("invalid glob `{glob}`: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::normalize_path;
+    use lambda_http::Body;
+
+    fn request(method: &str, path: &str, headers: &[(&str, &str)]) -> Request {
+        let mut builder = lambda_http::http::Request::builder()
+            .method(method)
+            .uri(path);
+
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+
+        builder.body(Body::Empty).unwrap()
+    }
+
+    fn check(rules: &Rules, method: &str, path: &str, headers: &[(&str, &str)]) -> Option<u16> {
+        rules
+            .check(&request(method, path, headers), path)
+            .map(|status| status.as_u16())
+    }
+
+    #[test]
+    fn default_rules_deny_protected_files() {
+        let rules = Rules::parse(DEFAULT_RULES).unwrap();
+
+        assert_eq!(
+            check(&rules, "GET", "/wp-content/plugins/hello.php", &[]),
+            Some(403)
+        );
+        assert_eq!(
+            check(&rules, "GET", "/wp-includes/version.php", &[]),
+            Some(403)
+        );
+        assert_eq!(check(&rules, "GET", "/composer.json", &[]), Some(403));
+        assert_eq!(
+            check(&rules, "GET", "/wp-content/.htaccess", &[]),
+            Some(403)
+        );
+        assert_eq!(check(&rules, "GET", "/wp-login.php", &[]), None);
+        assert_eq!(
+            check(&rules, "GET", "/wp-content/uploads/logo.png", &[]),
+            None
+        );
+    }
+
+    #[test]
+    fn default_rules_deny_hidden_and_backup_files() {
+        let rules = Rules::parse(DEFAULT_RULES).unwrap();
+
+        for path in [
+            "/.env",
+            "/.git/config",
+            "/wp-content/uploads/.user.ini",
+            "/wp-config.php.bak",
+            "/wp-config.php~",
+            "/wp-config.php.swp",
+            "/wp-config.php.orig",
+        ] {
+            assert_eq!(check(&rules, "GET", path, &[]), Some(403), "{path}");
+        }
+
+        assert_eq!(
+            check(&rules, "GET", "/.well-known/acme-challenge/token", &[]),
+            None
+        );
+        assert_eq!(
+            check(&rules, "GET", "/.well-known/assetlinks.json", &[]),
+            None
+        );
+        assert_eq!(
+            check(&rules, "GET", "/wp-content/uploads/report.old.pdf", &[]),
+            None
+        );
+    }
+
+    #[test]
+    fn default_rules_see_normalized_paths() {
+        let rules = Rules::parse(DEFAULT_RULES).unwrap();
+
+        for path in [
+            "//wp-content/plugins/x/evil.php",
+            "/./wp-content/plugins/x/evil.php",
+            "/wp-content//plugins/./x/evil.php",
+        ] {
+            let path = normalize_path(path).unwrap();
+
+            assert_eq!(check(&rules, "GET", &path, &[]), Some(403), "{path}");
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = Rules::parse(
+            r#"
+            [[rules]]
+            action = "allow"
+            path = "/wp-content/plugins/forms/endpoint.php"
+
+            [[rules]]
+            action = "deny"
+            path = "/wp-content/**.php"
+
+            [[rules]]
+            action = "deny"
+            path = "/xmlrpc.php"
+            methods = ["post"]
+            status = 404
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            check(&rules, "GET", "/wp-content/plugins/forms/endpoint.php", &[]),
+            None
+        );
+        assert_eq!(
+            check(&rules, "GET", "/wp-content/plugins/forms/admin.php", &[]),
+            Some(403)
+        );
+        assert_eq!(check(&rules, "POST", "/xmlrpc.php", &[]), Some(404));
+        assert_eq!(check(&rules, "GET", "/xmlrpc.php", &[]), None);
+    }
+
+    #[test]
+    fn matches_headers() {
+        let rules = Rules::parse(
+            r#"
+            [[rules]]
+            action = "deny"
+            headers = { User-Agent = "*badbot*" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            check(&rules, "GET", "/", &[("user-agent", "Mozilla BadBot/1.0")]),
+            Some(403)
+        );
+        assert_eq!(
+            check(&rules, "GET", "/", &[("user-agent", "Mozilla/5.0")]),
+            None
+        );
+        assert_eq!(check(&rules, "GET", "/", &[]), None);
+    }
+
+    #[test]
+    fn matches_ip_ranges() {
+        let range: IpRange = "10.0.0.0/8".parse().unwrap();
+
+        assert!(range.contains("10.1.2.3".parse().unwrap()));
+        assert!(!range.contains("11.1.2.3".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+
+        let range: IpRange = "2001:db8::/32".parse().unwrap();
+
+        assert!(range.contains("2001:db8::1".parse().unwrap()));
+        assert!("0.0.0.0/0"
+            .parse::<IpRange>()
+            .unwrap()
+            .contains("8.8.8.8".parse().unwrap()));
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        let invalid = [
+            "[[rules]]\naction = \"deny\"\npath = \"/a\"\nregex = \"/a\"",
+            "[[rules]]\naction = \"deny\"\nregex = \"(\"",
+            "[[rules]]\naction = \"deny\"\nips = [\"10.0.0.0/33\"]",
+            "[[rules]]\naction = \"deny\"\nstatus = 700",
+            "[[rules]]\naction = \"allow\"\nstatus = 403",
+            "[[rules]]\naction = \"deny\"\nmethods = [\"G E T\"]",
+            "[[rules]]\naction = \"block\"",
+            "[[rules]]\naction = \"deny\"\npaths = \"/a\"",
+        ];
+
+        for rules in invalid {
+            assert!(Rules::parse(rules).is_err(), "{rules}");
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use crate::error_page::{self, ErrorPages};
+use crate::streaming::StreamingBody;
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioIo;
+use lambda_http::aws_lambda_events::apigw::{
+    ApiGatewayV2httpRequestContext, ApiGatewayV2httpRequestContextHttpDescription,
+};
+use lambda_http::http::header::HOST;
+use lambda_http::http::request::Parts;
+use lambda_http::http::uri::{Authority, PathAndQuery, Uri};
+use lambda_http::request::RequestContext;
+use lambda_http::{Body, Error, Request, RequestExt, Response};
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+/// Serves HTTP on `address`, outside of Lambda. Requests are handed to
+/// `handler` like a Function URL would, so they go through the same pipeline,
+/// error pages included.
+pub async fn serve<H, F>(address: SocketAddr, pages: Arc<ErrorPages>, handler: H) -> io::Result<()>
+where
+    H: Fn(Request) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Result<Response<StreamingBody>, Error>> + Send + 'static,
+{
+    let listener = TcpListener::bind(address).await?;
+
+    info!("Serving HTTP on {}", listener.local_addr()?);
+
+    loop {
+        let (stream, client) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(error) => {
+                warn!("Failed to accept a connection: {}", error);
+                continue;
+            }
+        };
+
+        let handler = handler.clone();
+        let pages = pages.clone();
+
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |req| {
+                let handler = handler.clone();
+                let pages = pages.clone();
+
+                async move { Ok::<_, Infallible>(respond(req, client, &pages, handler).await) }
+            });
+
+            if let Err(error) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                warn!("Failed to serve {}: {}", client, error);
+            }
+        });
+    }
+}
+
+async fn respond<H, F>(
+    req: hyper::Request<Incoming>,
+    client: SocketAddr,
+    pages: &ErrorPages,
+    handler: H,
+) -> Response<StreamingBody>
+where
+    H: Fn(Request) -> F + Send + Sync + 'static,
+    F: Future<Output = Result<Response<StreamingBody>, Error>> + Send + 'static,
+{
+    let (parts, body) = req.into_parts();
+    let req = to_lambda_request(parts, client);
+
+    // The body is read along the handler, so failing to read it answers like
+    // a failed invocation in Lambda.
+    error_page::respond(pages, req, move |req| async move {
+        handler(with_body(req, body).await?).await
+    })
+    .await
+}
+
+// Adds what Lambda events carry: an absolute URI, the raw path and a request
+// context with the client address.
+fn to_lambda_request(mut parts: Parts, client: SocketAddr) -> Request {
+    let host = parts
+        .headers
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .filter(|host| host.parse::<Authority>().is_ok())
+        .unwrap_or("localhost")
+        .to_string();
+
+    let path_and_query = parts
+        .uri
+        .path_and_query()
+        .cloned()
+        .unwrap_or(PathAndQuery::from_static("/"));
+
+    parts.uri = Uri::builder()
+        .scheme("http")
+        .authority(host.as_str())
+        .path_and_query(path_and_query)
+        .build()
+        .expect("the host and path are valid");
+
+    let context = ApiGatewayV2httpRequestContext {
+        stage: Some("$default".into()),
+        domain_name: Some(host),
+        http: ApiGatewayV2httpRequestContextHttpDescription {
+            method: parts.method.clone(),
+            path: Some(parts.uri.path().into()),
+            protocol: Some(format!("{:?}", parts.version)),
+            source_ip: Some(client.ip().to_string()),
+            user_agent: None,
+        },
+        ..Default::default()
+    };
+
+    let raw_path = parts.uri.path().to_string();
+
+    Request::from_parts(parts, Body::Empty)
+        .with_raw_http_path(raw_path)
+        .with_request_context(RequestContext::ApiGatewayV2(context))
+}
+
+// Buffers the body, like Lambda events hold it.
+async fn with_body(req: Request, body: Incoming) -> Result<Request, Error> {
+    let body = body.collect().await?.to_bytes();
+
+    Ok(match body.is_empty() {
+        true => req,
+        false => req.map(|_| Body::from(body.to_vec())),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cgi::CgiParams;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    // Serves `handler` on a free port, returning its address.
+    async fn start<H, F>(handler: H) -> SocketAddr
+    where
+        H: Fn(Request) -> F + Clone + Send + Sync + 'static,
+        F: Future<Output = Result<Response<StreamingBody>, Error>> + Send + 'static,
+    {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        tokio::spawn(serve(address, Arc::default(), handler));
+
+        address
+    }
+
+    async fn get(address: SocketAddr, path: &str) -> String {
+        let mut stream = loop {
+            match TcpStream::connect(address).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        };
+
+        let request = format!(
+            "GET {path} HTTP/1.1\r\nHost: localhost:{}\r\nConnection: close\r\n\r\n",
+            address.port()
+        );
+
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_requests_through_the_handler() {
+        // Echoes the CGI variables the request turns into.
+        let address = start(|req: Request| async move {
+            let params = CgiParams::from_request(&req, "/mnt/wordpress", "/index.php");
+            let body = [
+                "REQUEST_URI",
+                "SERVER_NAME",
+                "SERVER_PORT",
+                "HTTPS",
+                "REMOTE_ADDR",
+            ]
+            .map(|name| format!("{name}={}", params.get(name).unwrap_or_default()))
+            .join("\n");
+
+            Ok(Response::new(StreamingBody::from(Body::from(body))))
+        })
+        .await;
+
+        let response = get(address, "/hello-world/?p=1").await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("REQUEST_URI=/hello-world/?p=1"));
+        assert!(response.contains("SERVER_NAME=localhost"));
+        assert!(response.contains(&format!("SERVER_PORT={}", address.port())));
+        assert!(response.contains("HTTPS=\n"));
+        assert!(response.contains("REMOTE_ADDR=127.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn answers_handler_errors_like_lambda() {
+        let address = start(|_: Request| async { Err("PHP is gone".into()) }).await;
+
+        let response = get(address, "/").await;
+
+        // Rendered with the error page, like in Lambda.
+        assert!(response.starts_with("HTTP/1.1 502 Bad Gateway"));
+        assert!(response.contains("<!DOCTYPE html>"));
+    }
+}
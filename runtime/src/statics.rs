@@ -0,0 +1,446 @@
+use crate::error_page;
+use crate::response::{lambda_body, MAX_BODY_SIZE};
+use lambda_http::http::header::{
+    ACCEPT_ENCODING, ACCEPT_RANGES, ALLOW, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE,
+    CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, RANGE, VARY,
+};
+use lambda_http::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use lambda_http::{Body, Error, Request, Response};
+use std::fs::Metadata;
+use std::io::{self, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::error;
+
+/// Precompressed variants looked up next to a file, in order of preference.
+const ENCODINGS: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// A file, or one of its precompressed variants, as sent to the client.
+struct StaticFile {
+    path: PathBuf,
+    encoding: Option<&'static str>,
+    metadata: Metadata,
+}
+
+impl StaticFile {
+    fn etag(&self) -> String {
+        let modified = modified_secs(&self.metadata);
+
+        match self.encoding {
+            Some(encoding) => format!("\"{:x}-{:x}-{}\"", self.metadata.len(), modified, encoding),
+            None => format!("\"{:x}-{:x}\"", self.metadata.len(), modified),
+        }
+    }
+
+    fn last_modified(&self) -> Option<String> {
+        self.metadata.modified().ok().map(httpdate::fmt_http_date)
+    }
+}
+
+/// Serves a file from the document root, without going through PHP.
+pub async fn serve(req: &Request, path: &Path) -> Result<Response<Body>, Error> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        let response = Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(ALLOW, "GET, HEAD")
+            .body(Body::Empty)
+            .map_err(Box::new)?;
+
+        return Ok(response);
+    }
+
+    let file = match find_variant(path, req.headers()).await? {
+        Some(file) => file,
+        None => return not_found(),
+    };
+
+    let etag = file.etag();
+    let last_modified = file.last_modified();
+
+    let mut builder = Response::builder()
+        .header(ACCEPT_RANGES, "bytes")
+        .header(ETAG, &etag)
+        .header(VARY, "Accept-Encoding");
+
+    if let Some(last_modified) = &last_modified {
+        builder = builder.header(LAST_MODIFIED, last_modified);
+    }
+
+    if is_not_modified(req.headers(), &etag, &file.metadata) {
+        let response = builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::Empty)
+            .map_err(Box::new)?;
+
+        return Ok(response);
+    }
+
+    let content_type = mime_guess::from_path(path).first_or_octet_stream();
+
+    builder = builder.header(CONTENT_TYPE, content_type.as_ref());
+
+    if let Some(encoding) = file.encoding {
+        builder = builder.header(CONTENT_ENCODING, encoding);
+    }
+
+    let size = file.metadata.len();
+
+    let range = match requested_range(req.headers(), &etag, last_modified.as_deref()) {
+        Some(range) => match resolve_range(range, size) {
+            Some(range) => {
+                builder = builder.status(StatusCode::PARTIAL_CONTENT).header(
+                    CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", range.start, range.end - 1, size),
+                );
+                range
+            }
+            None => {
+                let response = builder
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(CONTENT_RANGE, format!("bytes */{}", size))
+                    .body(Body::Empty)
+                    .map_err(Box::new)?;
+
+                return Ok(response);
+            }
+        },
+        None => 0..size,
+    };
+
+    builder = builder.header(CONTENT_LENGTH, range.end - range.start);
+
+    if req.method() == Method::HEAD {
+        return Ok(builder.body(Body::Empty).map_err(Box::new)?);
+    }
+
+    if range.end - range.start > MAX_BODY_SIZE {
+        error!(
+            "{} is too large for a Lambda response ({} bytes), use range requests",
+            path.display(),
+            range.end - range.start
+        );
+
+        let mut response = error_page::response(StatusCode::BAD_GATEWAY);
+        response
+            .headers_mut()
+            .insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+        return Ok(response);
+    }
+
+    let contents = read_range(&file.path, range).await?;
+    let body = lambda_body(builder.headers_ref(), &contents);
+
+    Ok(builder.body(body).map_err(Box::new)?)
+}
+
+// Picks the best precompressed variant accepted by the client, or the file
+// itself. `None` when the file doesn't exist.
+async fn find_variant(path: &Path, headers: &HeaderMap) -> io::Result<Option<StaticFile>> {
+    let accepted = headers
+        .get(ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    for (encoding, extension) in ENCODINGS {
+        if !accepts_encoding(accepted, encoding) {
+            continue;
+        }
+
+        let mut variant = path.as_os_str().to_owned();
+        variant.push(".");
+        variant.push(extension);
+
+        if let Some(metadata) = file_metadata(Path::new(&variant)).await? {
+            return Ok(Some(StaticFile {
+                path: variant.into(),
+                encoding: Some(encoding),
+                metadata,
+            }));
+        }
+    }
+
+    let file = file_metadata(path).await?.map(|metadata| StaticFile {
+        path: path.to_path_buf(),
+        encoding: None,
+        metadata,
+    });
+
+    Ok(file)
+}
+
+async fn file_metadata(path: &Path) -> io::Result<Option<Metadata>> {
+    match tokio::fs::metadata(path).await {
+        Ok(metadata) if metadata.is_file() => Ok(Some(metadata)),
+        Ok(_) => Ok(None),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+// Whether `Accept-Encoding` allows an encoding, `br;q=0` refuses it.
+fn accepts_encoding(accepted: &str, encoding: &str) -> bool {
+    accepted.split(',').any(|item| {
+        let mut parts = item.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+
+        let refused = parts.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .is_some_and(|quality| quality == 0.0)
+        });
+
+        name.eq_ignore_ascii_case(encoding) && !refused
+    })
+}
+
+fn is_not_modified(headers: &HeaderMap, etag: &str, metadata: &Metadata) -> bool {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+    // `If-None-Match` takes precedence over `If-Modified-Since` (RFC 9110, 13.2.2).
+    if let Some(if_none_match) = header(IF_NONE_MATCH) {
+        return if_none_match.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        });
+    }
+
+    header(IF_MODIFIED_SINCE)
+        .and_then(|since| httpdate::parse_http_date(since).ok())
+        .is_some_and(|since| modified_secs(metadata) <= secs_since_epoch(since))
+}
+
+/// A single `bytes` range, as requested: `start-end`, `start-` or `-suffix`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ByteRange {
+    FromTo(u64, u64),
+    From(u64),
+    Last(u64),
+}
+
+// Multiple ranges are not supported, the whole file is sent instead.
+fn requested_range(
+    headers: &HeaderMap,
+    etag: &str,
+    last_modified: Option<&str>,
+) -> Option<ByteRange> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+    // Ranges only apply to the representation the client already has.
+    if let Some(if_range) = header(IF_RANGE) {
+        if if_range != etag && Some(if_range) != last_modified {
+            return None;
+        }
+    }
+
+    let (start, end) = header(RANGE)?
+        .trim()
+        .strip_prefix("bytes=")?
+        .split_once('-')?;
+
+    match (start.trim(), end.trim()) {
+        ("", "") => None,
+        ("", suffix) => suffix.parse().ok().map(ByteRange::Last),
+        (start, "") => start.parse().ok().map(ByteRange::From),
+        (start, end) => Some(ByteRange::FromTo(start.parse().ok()?, end.parse().ok()?)),
+    }
+}
+
+// Resolves a range against the file size, `None` when it can't be satisfied.
+fn resolve_range(range: ByteRange, size: u64) -> Option<Range<u64>> {
+    let range = match range {
+        ByteRange::FromTo(start, end) if start <= end => start..(end + 1).min(size),
+        ByteRange::FromTo(..) => return None,
+        ByteRange::From(start) => start..size,
+        ByteRange::Last(0) => return None,
+        ByteRange::Last(length) => size.saturating_sub(length)..size,
+    };
+
+    (range.start < size).then_some(range)
+}
+
+async fn read_range(path: &Path, range: Range<u64>) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path).await?;
+    file.seek(SeekFrom::Start(range.start)).await?;
+
+    let mut contents = Vec::with_capacity((range.end - range.start) as usize);
+    file.take(range.end - range.start)
+        .read_to_end(&mut contents)
+        .await?;
+
+    Ok(contents)
+}
+
+fn modified_secs(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .map(secs_since_epoch)
+        .unwrap_or_default()
+}
+
+fn secs_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn not_found() -> Result<Response<Body>, Error> {
+    Ok(error_page::response(StatusCode::NOT_FOUND))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::DocumentRoot;
+    use std::fs;
+
+    fn stylesheet(name: &str) -> DocumentRoot {
+        DocumentRoot::new(&format!("statics-{name}")).file("style.css", "body { color: red; }")
+    }
+
+    fn request(method: &str, headers: &[(&str, &str)]) -> Request {
+        let mut builder = lambda_http::http::Request::builder()
+            .method(method)
+            .uri("/style.css");
+
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+
+        builder.body(Body::Empty).unwrap()
+    }
+
+    async fn get(path: &Path, headers: &[(&str, &str)]) -> Response<Body> {
+        serve(&request("GET", headers), path).await.unwrap()
+    }
+
+    fn header<'a>(response: &'a Response<Body>, name: &str) -> Option<&'a str> {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    #[tokio::test]
+    async fn serves_files_with_validators() {
+        let root = stylesheet("validators");
+        let response = get(&root.join("style.css"), &[]).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, "content-type"), Some("text/css"));
+        assert_eq!(header(&response, "content-length"), Some("20"));
+        assert_eq!(header(&response, "accept-ranges"), Some("bytes"));
+        assert!(header(&response, "etag").is_some_and(|etag| etag.starts_with("\"14-")));
+        assert!(header(&response, "last-modified").is_some_and(|date| date.ends_with(" GMT")));
+        assert_eq!(response.body(), &Body::Text("body { color: red; }".into()));
+
+        let missing = get(&root.join("missing.css"), &[]).await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+        let post = serve(&request("POST", &[]), &root.join("style.css"))
+            .await
+            .unwrap();
+        assert_eq!(post.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn answers_not_modified() {
+        let root = stylesheet("not-modified");
+        let path = root.join("style.css");
+
+        let etag = header(&get(&path, &[]).await, "etag").unwrap().to_string();
+
+        let response = get(&path, &[("if-none-match", &etag)]).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.body(), &Body::Empty);
+
+        let response = get(&path, &[("if-none-match", &format!("W/{etag}"))]).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = get(&path, &[("if-none-match", "\"other\"")]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn serves_ranges() {
+        let root = stylesheet("ranges");
+        let path = root.join("style.css");
+
+        let response = get(&path, &[("range", "bytes=0-3")]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(header(&response, "content-range"), Some("bytes 0-3/20"));
+        assert_eq!(header(&response, "content-length"), Some("4"));
+        assert_eq!(response.body(), &Body::Text("body".into()));
+
+        let response = get(&path, &[("range", "bytes=-6")]).await;
+        assert_eq!(header(&response, "content-range"), Some("bytes 14-19/20"));
+
+        let response = get(&path, &[("range", "bytes=30-")]).await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(header(&response, "content-range"), Some("bytes */20"));
+
+        let etag = header(&response, "etag").unwrap().to_string();
+
+        let response = get(&path, &[("range", "bytes=0-3"), ("if-range", &etag)]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+
+        // A stale `If-Range` gets the whole file.
+        let response = get(&path, &[("range", "bytes=0-3"), ("if-range", "\"stale\"")]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, "content-length"), Some("20"));
+    }
+
+    #[tokio::test]
+    async fn prefers_precompressed_variants() {
+        let root = stylesheet("variants");
+        let path = root.join("style.css");
+
+        fs::write(root.join("style.css.br"), [0xce, 0xb2, 0x00]).unwrap();
+        fs::write(root.join("style.css.gz"), [0x1f, 0x8b, 0x08]).unwrap();
+
+        let response = get(&path, &[("accept-encoding", "gzip, br")]).await;
+        assert_eq!(header(&response, "content-encoding"), Some("br"));
+        assert_eq!(header(&response, "content-type"), Some("text/css"));
+        assert_eq!(header(&response, "vary"), Some("Accept-Encoding"));
+        assert!(header(&response, "etag").is_some_and(|etag| etag.ends_with("-br\"")));
+        assert_eq!(response.body(), &Body::Binary(vec![0xce, 0xb2, 0x00]));
+
+        let response = get(&path, &[("accept-encoding", "br;q=0, gzip")]).await;
+        assert_eq!(header(&response, "content-encoding"), Some("gzip"));
+        assert_eq!(response.body(), &Body::Binary(vec![0x1f, 0x8b, 0x08]));
+
+        let response = get(&path, &[]).await;
+        assert_eq!(header(&response, "content-encoding"), None);
+    }
+
+    #[tokio::test]
+    async fn refuses_files_too_large_for_lambda() {
+        let root = stylesheet("large");
+        let path = root.join("video.mp4");
+
+        fs::File::create(&path)
+            .unwrap()
+            .set_len(MAX_BODY_SIZE + 1)
+            .unwrap();
+
+        let response = get(&path, &[]).await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(header(&response, "accept-ranges"), Some("bytes"));
+
+        let response = get(&path, &[("range", "bytes=0-1023")]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(header(&response, "content-type"), Some("video/mp4"));
+        assert_eq!(response.body(), &Body::Binary(vec![0; 1024]));
+
+        let head = serve(&request("HEAD", &[]), &path).await.unwrap();
+        assert_eq!(head.status(), StatusCode::OK);
+        assert_eq!(
+            header(&head, "content-length"),
+            Some((MAX_BODY_SIZE + 1).to_string().as_str())
+        );
+    }
+}
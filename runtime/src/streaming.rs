@@ -0,0 +1,277 @@
+use crate::backend::{BackendError, Output};
+use crate::response::head_end;
+use bytes::Bytes;
+use http_body::{Frame, SizeHint};
+use lambda_http::{Body, Error};
+use std::fmt;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{ready, Context, Poll};
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Error)]
+pub enum StreamingError {
+    #[error("Unknown response mode `{0}`, expected one of: buffered, streaming")]
+    UnknownMode(String),
+}
+
+/// How responses are sent back to Lambda.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResponseMode {
+    /// The whole response at once, up to 6 MB. The only mode API Gateway
+    /// supports.
+    #[default]
+    Buffered,
+    /// Headers as soon as PHP sends them, then the body as it is written.
+    /// Needs a Function URL with the `RESPONSE_STREAM` invoke mode. A fatal
+    /// error once the headers are sent cuts the body short, where a buffered
+    /// response would be a 500.
+    Streaming,
+}
+
+impl FromStr for ResponseMode {
+    type Err = StreamingError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "buffered" => Ok(Self::Buffered),
+            "streaming" => Ok(Self::Streaming),
+            _ => Err(StreamingError::UnknownMode(value.into())),
+        }
+    }
+}
+
+impl fmt::Display for ResponseMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Buffered => write!(f, "buffered"),
+            Self::Streaming => write!(f, "streaming"),
+        }
+    }
+}
+
+/// Chunks of PHP output, ending with an error if PHP failed midway.
+pub type OutputReceiver = mpsc::Receiver<Result<Output, BackendError>>;
+
+/// PHP output up to the end of the headers block.
+#[derive(Debug, Default)]
+pub struct Head {
+    pub headers: String,
+    /// The start of the body, read along the headers.
+    pub body: Bytes,
+    /// Error output written before the headers.
+    pub stderr: Vec<u8>,
+}
+
+/// Body of a streamed response.
+pub enum StreamingBody {
+    /// A response built in full, like static files and redirects.
+    Buffered(Body),
+    /// The body PHP is writing, starting with what was read along the
+    /// headers.
+    Output {
+        first: Option<Bytes>,
+        receiver: OutputReceiver,
+    },
+}
+
+impl StreamingBody {
+    pub fn output(first: Bytes, receiver: OutputReceiver) -> Self {
+        Self::Output {
+            first: Some(first).filter(|first| !first.is_empty()),
+            receiver,
+        }
+    }
+}
+
+impl From<Body> for StreamingBody {
+    fn from(body: Body) -> Self {
+        Self::Buffered(body)
+    }
+}
+
+impl http_body::Body for StreamingBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.get_mut() {
+            Self::Buffered(body) => Pin::new(body).poll_frame(cx),
+            Self::Output { first, receiver } => {
+                if let Some(first) = first.take() {
+                    return Poll::Ready(Some(Ok(Frame::data(first))));
+                }
+
+                loop {
+                    return match ready!(receiver.poll_recv(cx)) {
+                        Some(Ok(Output::Stdout(chunk))) => {
+                            Poll::Ready(Some(Ok(Frame::data(chunk))))
+                        }
+                        // Logged once PHP is done.
+                        Some(Ok(Output::Stderr(_))) => continue,
+                        Some(Err(error)) => Poll::Ready(Some(Err(error.into()))),
+                        None => Poll::Ready(None),
+                    };
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self {
+            Self::Buffered(body) => body.size_hint(),
+            Self::Output { .. } => SizeHint::default(),
+        }
+    }
+}
+
+/// Reads PHP output up to the end of the headers block, or the whole output
+/// as body when there are no headers.
+pub async fn read_head(receiver: &mut OutputReceiver) -> Result<Head, BackendError> {
+    let mut output = Vec::new();
+    let mut stderr = Vec::new();
+
+    while let Some(chunk) = receiver.recv().await {
+        let chunk = match chunk? {
+            Output::Stdout(chunk) => chunk,
+            Output::Stderr(chunk) => {
+                stderr.extend_from_slice(&chunk);
+                continue;
+            }
+        };
+
+        output.extend_from_slice(&chunk);
+
+        if let Some((position, length)) = head_end(&output) {
+            return Ok(Head {
+                headers: String::from_utf8_lossy(&output[..position]).into_owned(),
+                body: Bytes::copy_from_slice(&output[position + length..]),
+                stderr,
+            });
+        }
+    }
+
+    Ok(Head {
+        body: output.into(),
+        stderr,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body::Body as _;
+
+    async fn collect(mut body: StreamingBody) -> Result<Vec<u8>, Error> {
+        let mut collected = Vec::new();
+
+        while let Some(frame) = std::future::poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await
+        {
+            if let Ok(data) = frame?.into_data() {
+                collected.extend_from_slice(&data);
+            }
+        }
+
+        Ok(collected)
+    }
+
+    fn stdout(chunk: &'static str) -> Result<Output, BackendError> {
+        Ok(Output::Stdout(Bytes::from(chunk)))
+    }
+
+    #[tokio::test]
+    async fn streams_output_after_headers() {
+        let (sender, mut receiver) = mpsc::channel(8);
+
+        tokio::spawn(async move {
+            for chunk in [
+                "Status: 201\r\nX-Cache: ",
+                "miss\r",
+                "\n\r\nhello",
+                ", world",
+            ] {
+                sender.send(stdout(chunk)).await.unwrap();
+            }
+        });
+
+        let head = read_head(&mut receiver).await.unwrap();
+
+        assert_eq!(head.headers, "Status: 201\r\nX-Cache: miss");
+        assert_eq!(head.body, "hello");
+
+        let body = collect(StreamingBody::output(head.body, receiver))
+            .await
+            .unwrap();
+
+        assert_eq!(body, b"hello, world");
+    }
+
+    #[tokio::test]
+    async fn treats_output_without_headers_as_body() {
+        let (sender, mut receiver) = mpsc::channel(8);
+
+        sender.send(stdout("plain output")).await.unwrap();
+        drop(sender);
+
+        let head = read_head(&mut receiver).await.unwrap();
+
+        assert_eq!(head.headers, "");
+        assert_eq!(head.body, "plain output");
+    }
+
+    #[tokio::test]
+    async fn keeps_the_errors_written_before_the_headers() {
+        let (sender, mut receiver) = mpsc::channel(8);
+
+        let stderr = |chunk| Ok(Output::Stderr(Bytes::from(chunk)));
+
+        sender.send(stderr("PHP Fatal error:  boom")).await.unwrap();
+        sender
+            .send(stdout("Status: 200\r\n\r\nhello"))
+            .await
+            .unwrap();
+        sender.send(stderr("PHP Warning:  late")).await.unwrap();
+        sender.send(stdout(", world")).await.unwrap();
+        drop(sender);
+
+        let head = read_head(&mut receiver).await.unwrap();
+
+        assert_eq!(head.stderr, b"PHP Fatal error:  boom");
+
+        let body = collect(StreamingBody::output(head.body, receiver))
+            .await
+            .unwrap();
+
+        assert_eq!(body, b"hello, world");
+    }
+
+    #[tokio::test]
+    async fn ends_with_backend_errors() {
+        let (sender, receiver) = mpsc::channel(8);
+
+        sender.send(stdout("partial")).await.unwrap();
+        sender
+            .send(Err(BackendError::Execution("PHP crashed".into())))
+            .await
+            .unwrap();
+        drop(sender);
+
+        assert!(collect(StreamingBody::output(Bytes::new(), receiver))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!(
+            "Streaming".parse::<ResponseMode>().unwrap(),
+            ResponseMode::Streaming
+        );
+        assert!("chunked".parse::<ResponseMode>().is_err());
+    }
+}
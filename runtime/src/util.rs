@@ -0,0 +1,17 @@
+/// Compares secrets in a time that doesn't depend on where they differ.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_secrets() {
+        assert!(constant_time_eq(b"s3cr3t", b"s3cr3t"));
+        assert!(!constant_time_eq(b"s3cr3t", b"s3cr3T"));
+        assert!(!constant_time_eq(b"s3cr3t", b"s3cr3"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
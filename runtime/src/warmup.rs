@@ -0,0 +1,216 @@
+use crate::backend::{Backend, BackendRequest};
+use crate::cgi::CgiParams;
+use crate::config::RuntimeConfig;
+use crate::deadline;
+use lambda_http::{Body, Error, Request, Response};
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How Lambda is starting the execution environment, from
+/// `AWS_LAMBDA_INITIALIZATION_TYPE`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InitType {
+    /// A cold start, with a client waiting for the first response.
+    #[default]
+    OnDemand,
+    /// Ahead of any request, so there is time to warm up further.
+    ProvisionedConcurrency,
+    SnapStart,
+}
+
+impl InitType {
+    pub fn from_env() -> Self {
+        match std::env::var("AWS_LAMBDA_INITIALIZATION_TYPE").as_deref() {
+            Ok("provisioned-concurrency") => Self::ProvisionedConcurrency,
+            Ok("snap-start") => Self::SnapStart,
+            _ => Self::OnDemand,
+        }
+    }
+}
+
+impl fmt::Display for InitType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OnDemand => write!(f, "on-demand"),
+            Self::ProvisionedConcurrency => write!(f, "provisioned-concurrency"),
+            Self::SnapStart => write!(f, "snap-start"),
+        }
+    }
+}
+
+/// Requests run during init, so the first clients don't pay for PHP filling
+/// opcache.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Warmup {
+    /// URL paths requested through the handler, like `/` and `/wp-login.php`.
+    paths: Vec<String>,
+    /// A script compiling files into opcache, like one for `opcache.preload`.
+    preload: Option<PathBuf>,
+    /// Time after which the remaining paths are skipped, `None` to run them
+    /// all.
+    timeout: Option<Duration>,
+}
+
+impl Warmup {
+    pub const TIMEOUT: Duration = Duration::from_secs(3);
+    /// Bounds the preload script when the warmup itself is not.
+    const PRELOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+    /// The warmup set in `config`. On-demand inits stop warming up after
+    /// `warmup_timeout`, provisioned concurrency runs it all.
+    pub fn from_config(config: &RuntimeConfig, init_type: InitType) -> Self {
+        Self::new(
+            config.warmup_paths.clone(),
+            config.warmup_preload.clone(),
+            config.warmup_timeout,
+            init_type,
+        )
+    }
+
+    fn new(
+        paths: Vec<String>,
+        preload: Option<PathBuf>,
+        timeout: Duration,
+        init_type: InitType,
+    ) -> Self {
+        Self {
+            paths,
+            preload,
+            timeout: match init_type {
+                InitType::ProvisionedConcurrency => None,
+                _ => Some(timeout),
+            },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.preload.is_none()
+    }
+
+    /// Runs the preload script through `backend`, then requests the paths
+    /// through `handler`. Failures are logged, they don't stop the runtime.
+    /// Returns how many requests ran.
+    pub async fn run<H, F>(&self, backend: &Backend, document_root: &str, handler: H) -> usize
+    where
+        H: Fn(Request) -> F,
+        F: Future<Output = Result<Response<Body>, Error>>,
+    {
+        let started = Instant::now();
+        let mut count = 0;
+
+        if let Some(preload) = &self.preload {
+            let mut params = CgiParams::from_request(&request("/"), document_root, "/");
+            params.insert("SCRIPT_FILENAME", preload.to_string_lossy());
+
+            let request = BackendRequest {
+                params,
+                ..Default::default()
+            };
+
+            let budget = self.timeout.unwrap_or(Self::PRELOAD_TIMEOUT);
+
+            match deadline::execute_for(backend, request, budget).await {
+                Ok(response) if !response.stderr.is_empty() => warn!(
+                    "Preload script wrote errors: {}",
+                    String::from_utf8_lossy(&response.stderr)
+                ),
+                Ok(_) => {}
+                Err(error) => warn!("Failed to run the preload script: {}", error),
+            }
+
+            count += 1;
+        }
+
+        count + self.request_paths(started, handler).await
+    }
+
+    async fn request_paths<H, F>(&self, started: Instant, handler: H) -> usize
+    where
+        H: Fn(Request) -> F,
+        F: Future<Output = Result<Response<Body>, Error>>,
+    {
+        let mut count = 0;
+
+        for path in &self.paths {
+            if self
+                .timeout
+                .is_some_and(|timeout| started.elapsed() >= timeout)
+            {
+                warn!("Warmup timed out, skipping {}", path);
+                continue;
+            }
+
+            match handler(request(path)).await {
+                Ok(response) => info!("Warmed up {} ({})", path, response.status().as_u16()),
+                Err(error) => warn!("Failed to warm up {}: {}", path, error),
+            }
+
+            count += 1;
+        }
+
+        count
+    }
+}
+
+// A bare GET request, like one coming from a Function URL without headers.
+fn request(path: &str) -> Request {
+    let mut req = Request::new(Body::Empty);
+    *req.uri_mut() = format!("https://localhost{path}")
+        .parse()
+        .unwrap_or_default();
+
+    req
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn translates_warmup_requests() {
+        let params =
+            CgiParams::from_request(&request("/wp-login.php"), "/mnt/wordpress", "/wp-login.php");
+
+        assert_eq!(params.get("REQUEST_METHOD"), Some("GET"));
+        assert_eq!(params.get("REQUEST_URI"), Some("/wp-login.php"));
+        assert_eq!(params.get("SERVER_NAME"), Some("localhost"));
+        assert_eq!(params.get("HTTPS"), Some("on"));
+    }
+
+    #[test]
+    fn goes_further_with_provisioned_concurrency() {
+        let timeout = |init_type| Warmup::new(Vec::new(), None, Warmup::TIMEOUT, init_type).timeout;
+
+        assert_eq!(timeout(InitType::OnDemand), Some(Warmup::TIMEOUT));
+        assert_eq!(timeout(InitType::ProvisionedConcurrency), None);
+    }
+
+    #[tokio::test]
+    async fn stops_on_demand_warmup_after_the_timeout() {
+        let warmup = Warmup {
+            paths: vec!["/".into(), "/wp-login.php".into(), "/wp-admin/".into()],
+            preload: None,
+            timeout: Some(Duration::from_millis(20)),
+        };
+
+        let requested = Mutex::new(Vec::new());
+
+        let handler = |req: Request| {
+            requested.lock().unwrap().push(req.uri().path().to_string());
+
+            async {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                Ok(Response::new(Body::Empty))
+            }
+        };
+
+        let count = warmup.request_paths(Instant::now(), handler).await;
+
+        assert_eq!(count, 1);
+        assert_eq!(*requested.lock().unwrap(), ["/"]);
+    }
+}